@@ -0,0 +1,168 @@
+use std::ops::Range;
+
+use crate::segment;
+use crate::warc::DocJson;
+
+const DEFAULT_WORDS: usize = 200;
+const DEFAULT_SENTENCES: usize = 8;
+const DEFAULT_PARAGRAPHS: usize = 3;
+
+// What passages are counted in, see `segment`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkUnit {
+    // runs of non-whitespace, roughly the tokens of the body field
+    Words,
+    Sentences,
+    Paragraphs,
+}
+
+// Splits bodies into overlapping passages of `size` units, each starting
+// `size - overlap` units after the one before, `--chunk`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunker {
+    pub unit: ChunkUnit,
+    pub size: usize,
+    pub overlap: usize,
+}
+
+// A passage of a body, as byte range for slicing and as char range, the
+// offsets reported with the passage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    pub bytes: Range<usize>,
+    pub chars: Range<usize>,
+}
+
+impl Chunker {
+    // Parses the unit with an optional size, followed by an optional
+    // overlap, e.g. `words=200,overlap=50` or `sentences=5,overlap=1`. The
+    // overlap defaults to a quarter of the size.
+    pub fn parse(spec: &str) -> Result<Chunker, String> {
+        let mut parts = spec.split(',').map(|part| part.trim());
+        let count = |name: &str, value: &str| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or_else(|| format!("chunk setting {} needs a count", name))
+        };
+        let unit = parts.next().unwrap_or_default();
+        let (unit, size) = match unit.split_once('=') {
+            Some((name, value)) => (name, Some(count(name, value)?)),
+            None => (unit, None),
+        };
+        let (unit, size) = match unit {
+            "words" => (ChunkUnit::Words, size.unwrap_or(DEFAULT_WORDS)),
+            "sentences" => (ChunkUnit::Sentences, size.unwrap_or(DEFAULT_SENTENCES)),
+            "paragraphs" => (ChunkUnit::Paragraphs, size.unwrap_or(DEFAULT_PARAGRAPHS)),
+            _ => return Err(format!("unknown chunk unit {}", unit)),
+        };
+        let mut overlap = size / 4;
+        for setting in parts {
+            match setting.split_once('=') {
+                Some(("overlap", value)) => {
+                    overlap = value
+                        .parse::<usize>()
+                        .map_err(|_| "chunk setting overlap needs a count".to_string())?
+                }
+                _ => return Err(format!("unknown chunk setting {}", setting)),
+            }
+        }
+        if overlap >= size {
+            return Err(format!(
+                "chunk overlap {} must be less than the size {}",
+                overlap, size
+            ));
+        }
+        Ok(Chunker {
+            unit,
+            size,
+            overlap,
+        })
+    }
+
+    // The passages of `body` in order. A body without any words is a single
+    // empty passage, so every document keeps at least one.
+    pub fn passages(&self, body: &str) -> Vec<Passage> {
+        let units = match self.unit {
+            ChunkUnit::Words => segment::words(body),
+            ChunkUnit::Sentences => segment::sentences(body),
+            ChunkUnit::Paragraphs => segment::paragraphs(body),
+        };
+        if units.is_empty() {
+            return vec![Passage {
+                bytes: 0..0,
+                chars: 0..0,
+            }];
+        }
+        let step = self.size - self.overlap;
+        let mut ranges = Vec::new();
+        let mut first = 0;
+        loop {
+            let last = (first + self.size).min(units.len()) - 1;
+            ranges.push(units[first].start..units[last].end);
+            if last + 1 == units.len() {
+                break;
+            }
+            first += step;
+        }
+        // byte offsets to char offsets, counting each part of the body once
+        let mut offsets: Vec<usize> = ranges.iter().flat_map(|r| vec![r.start, r.end]).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let (mut byte, mut chars) = (0, 0);
+        let char_offsets: Vec<(usize, usize)> = offsets
+            .into_iter()
+            .map(|offset| {
+                chars += body[byte..offset].chars().count();
+                byte = offset;
+                (offset, chars)
+            })
+            .collect();
+        let to_chars = |offset: usize| {
+            let i = char_offsets
+                .binary_search_by_key(&offset, |(byte, _)| *byte)
+                .expect("offset counted");
+            char_offsets[i].1
+        };
+        ranges
+            .into_iter()
+            .map(|bytes| Passage {
+                chars: to_chars(bytes.start)..to_chars(bytes.end),
+                bytes,
+            })
+            .collect()
+    }
+
+    // One document per passage of `doc`, sharing all but the body with it.
+    // Each has the `parent_id` of `doc`, its `record_id` or else its `uri`,
+    // the `record_id` `<parent_id>#<passage>` and the passage's char offsets
+    // in the body of `doc`.
+    pub fn chunk(&self, doc: DocJson) -> Vec<DocJson> {
+        let parent = doc.record_id.clone().unwrap_or_else(|| doc.uri.clone());
+        self.passages(&doc.body)
+            .into_iter()
+            .enumerate()
+            .map(|(ordinal, passage)| DocJson {
+                record_id: Some(format!("{}#{}", parent, ordinal)),
+                parent_id: Some(parent.clone()),
+                passage: Some(ordinal as u64),
+                passage_start: Some(passage.chars.start as u64),
+                passage_end: Some(passage.chars.end as u64),
+                body: doc.body[passage.bytes].to_string(),
+                ..doc.clone()
+            })
+            .collect()
+    }
+}
+
+// The text of a passage given by the char offsets `start` and `end` in
+// `body`, e.g. to cut a passage from a body read back from its record.
+pub fn passage_text(body: &str, start: u64, end: u64) -> &str {
+    let byte = |offset: u64| {
+        body.char_indices()
+            .nth(offset as usize)
+            .map_or(body.len(), |(i, _)| i)
+    };
+    &body[byte(start)..byte(end)]
+}
@@ -0,0 +1,272 @@
+use std;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, ListArray, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Float32Type, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::sink::{DocSink, OutputCompression, OutputOptions};
+use crate::warc::DocJson;
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+pub fn doc_schema(source: &str) -> Schema {
+    let fields = vec![
+        Field::new("uri", DataType::Utf8, false),
+        Field::new("title", DataType::Utf8, false),
+        Field::new("body", DataType::Utf8, false),
+        Field::new("date", DataType::Utf8, false),
+        Field::new("record_id", DataType::Utf8, true),
+        Field::new("language", DataType::Utf8, true),
+        Field::new("content_length", DataType::UInt64, true),
+        Field::new("host", DataType::Utf8, true),
+        Field::new("scheme", DataType::Utf8, true),
+        Field::new("domain", DataType::Utf8, true),
+        Field::new("tld", DataType::Utf8, true),
+        Field::new("content_hash", DataType::Utf8, true),
+        Field::new("block_digest", DataType::Utf8, true),
+        Field::new("simhash", DataType::UInt64, true),
+        Field::new("duplicate_of", DataType::UInt64, true),
+        Field::new(
+            "embedding",
+            DataType::List(Arc::new(Field::new("item", DataType::Float32, true))),
+            true,
+        ),
+        Field::new("parent_id", DataType::Utf8, true),
+        Field::new("passage", DataType::UInt64, true),
+        Field::new("passage_start", DataType::UInt64, true),
+        Field::new("passage_end", DataType::UInt64, true),
+    ];
+    let mut metadata = HashMap::new();
+    metadata.insert("warc_indexer.source".to_string(), source.to_string());
+    metadata.insert(
+        "warc_indexer.version".to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+    );
+    Schema::new_with_metadata(fields, metadata)
+}
+
+// Writes the documents of one input file into a single parquet file, one
+// column per `DocJson` field. The arrow schema, including the source type and
+// indexer version, is kept in the file's key-value metadata.
+pub struct ParquetSink {
+    schema: SchemaRef,
+    writer: ArrowWriter<File>,
+}
+
+impl ParquetSink {
+    pub fn create(path: PathBuf, options: &OutputOptions, source: &str) -> io::Result<ParquetSink> {
+        let schema = Arc::new(doc_schema(source));
+        let compression = match options.compression {
+            OutputCompression::None => Compression::UNCOMPRESSED,
+            OutputCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            OutputCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let properties = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(options.row_group_size)
+            .build();
+        let file = File::create(&path)?;
+        let writer =
+            ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(to_io_error)?;
+        Ok(ParquetSink { schema, writer })
+    }
+}
+
+impl DocSink for ParquetSink {
+    fn write_batch(&mut self, batch: &[DocJson]) -> io::Result<()> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                batch.iter().map(|doc| &doc.uri),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                batch.iter().map(|doc| &doc.title),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                batch.iter().map(|doc| &doc.body),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                batch.iter().map(|doc| &doc.date),
+            )),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.record_id.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.language.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.content_length)
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.host.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.scheme.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.domain.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.tld.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.content_hash.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.block_digest.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(batch.iter().map(|doc| doc.simhash).collect::<UInt64Array>()),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.duplicate_of)
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(
+                batch.iter().map(|doc| {
+                    doc.embedding
+                        .as_ref()
+                        .map(|vector| vector.iter().map(|x| Some(*x)))
+                }),
+            )),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.parent_id.as_deref())
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(batch.iter().map(|doc| doc.passage).collect::<UInt64Array>()),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.passage_start)
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(
+                batch
+                    .iter()
+                    .map(|doc| doc.passage_end)
+                    .collect::<UInt64Array>(),
+            ),
+        ];
+        let record_batch =
+            RecordBatch::try_new(self.schema.clone(), columns).map_err(to_io_error)?;
+        self.writer.write(&record_batch).map_err(to_io_error)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.writer.close().map_err(to_io_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::sink::{self, OutputFormat};
+
+    fn doc(i: usize) -> DocJson {
+        DocJson {
+            uri: format!("http://www.example.com/{}", i),
+            body: format!("body {}", i),
+            record_id: Some(format!("<urn:uuid:{}>", i)),
+            // every other document has no language
+            language: Some("eng".to_string()).filter(|_| i % 2 == 0),
+            content_length: Some(100 + i as u64),
+            host: Some("www.example.com".to_string()),
+            ..DocJson::default()
+        }
+    }
+
+    #[test]
+    fn parquet_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = OutputOptions {
+            format: OutputFormat::Parquet,
+            row_group_size: 4,
+            ..OutputOptions::default()
+        };
+        let mut sink = sink::create(dir.path().join("test.warc.json"), &options, "WARC").unwrap();
+        let docs: Vec<DocJson> = (0..10).map(doc).collect();
+        for batch in docs.chunks(3) {
+            sink.write_batch(batch).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let file = File::open(dir.path().join("test.warc.parquet")).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        // 10 rows in groups of at most 4
+        assert_eq!(builder.metadata().num_row_groups(), 3);
+        let metadata = builder.schema().metadata();
+        assert_eq!(metadata["warc_indexer.source"], "WARC");
+        assert_eq!(metadata["warc_indexer.version"], env!("CARGO_PKG_VERSION"));
+
+        let mut record_ids = Vec::new();
+        let mut languages = Vec::new();
+        let mut content_lengths = Vec::new();
+        let mut hosts = Vec::new();
+        for batch in builder.build().unwrap() {
+            let batch = batch.unwrap();
+            let strings = |name: &str| {
+                let column = batch.column_by_name(name).unwrap();
+                let column = column.as_any().downcast_ref::<StringArray>().unwrap();
+                (0..column.len())
+                    .map(|i| Some(column.value(i).to_string()).filter(|_| column.is_valid(i)))
+                    .collect::<Vec<_>>()
+            };
+            record_ids.extend(strings("record_id"));
+            languages.extend(strings("language"));
+            hosts.extend(strings("host"));
+            let column = batch.column_by_name("content_length").unwrap();
+            let column = column.as_any().downcast_ref::<UInt64Array>().unwrap();
+            content_lengths.extend(column.values().iter().cloned());
+        }
+        let expected = |field: fn(&DocJson) -> Option<String>| -> Vec<Option<String>> {
+            docs.iter().map(field).collect()
+        };
+        assert_eq!(record_ids, expected(|doc| doc.record_id.clone()));
+        assert_eq!(languages, expected(|doc| doc.language.clone()));
+        assert_eq!(languages[1], None);
+        assert_eq!(hosts, expected(|doc| doc.host.clone()));
+        assert_eq!(content_lengths, (100..110).collect::<Vec<u64>>());
+    }
+}
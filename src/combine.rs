@@ -0,0 +1,231 @@
+use std;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use tantivy::schema::{Field, Schema, Value};
+use tantivy::{DocAddress, Document, Index};
+
+use crate::dates;
+use crate::schema;
+use crate::schema::DocFields;
+use crate::tokenizers;
+use crate::warc::DocJson;
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// Reads the documents of an index back as `DocJson`, so they can be indexed
+// again with `DocFields::document`, which also rebuilds the fields that are
+// not stored, e.g. the `site` facet.
+struct StoredDocs {
+    schema: Schema,
+    // `title` and `body` and their per-language variants
+    titles: Vec<Field>,
+    bodies: Vec<Field>,
+}
+
+impl StoredDocs {
+    fn new(schema: &Schema) -> StoredDocs {
+        let indexed_text = |prefix: &str| -> Vec<Field> {
+            schema
+                .fields()
+                .filter(|(_, entry)| {
+                    let name = entry.name();
+                    entry.is_indexed()
+                        && (name == prefix || name.starts_with(&format!("{}_", prefix)))
+                })
+                .map(|(field, _)| field)
+                .collect()
+        };
+        StoredDocs {
+            titles: indexed_text("title"),
+            bodies: indexed_text("body"),
+            schema: schema.clone(),
+        }
+    }
+
+    fn value<'a>(&self, doc: &'a Document, name: &str) -> Option<&'a Value> {
+        doc.get_first(self.schema.get_field(name)?)
+    }
+
+    fn text(&self, doc: &Document, name: &str) -> Option<String> {
+        self.value(doc, name)
+            .and_then(|value| value.text())
+            .map(|text| text.to_string())
+    }
+
+    fn number(&self, doc: &Document, name: &str) -> Option<u64> {
+        match self.value(doc, name) {
+            Some(Value::U64(number)) => Some(*number),
+            _ => None,
+        }
+    }
+
+    fn first_text(doc: &Document, fields: &[Field]) -> Option<String> {
+        fields
+            .iter()
+            .find_map(|field| doc.get_first(*field).and_then(|value| value.text()))
+            .map(|text| text.to_string())
+    }
+
+    // The body stored in full or compressed, see `stores_bodies`.
+    fn body(&self, doc: &Document) -> io::Result<String> {
+        if let Some(body) = StoredDocs::first_text(doc, &self.bodies) {
+            return Ok(body);
+        }
+        match self.value(doc, "body_zstd") {
+            Some(Value::Bytes(compressed)) => {
+                let body = zstd::decode_all(&compressed[..])?;
+                Ok(String::from_utf8_lossy(&body).into_owned())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "document {} has no stored body",
+                    self.text(doc, "uri").unwrap_or_default()
+                ),
+            )),
+        }
+    }
+
+    fn doc_json(&self, doc: &Document) -> io::Result<DocJson> {
+        let date = match self.value(doc, "date") {
+            Some(Value::Date(date)) => dates::format(*date),
+            _ => String::new(),
+        };
+        Ok(DocJson {
+            uri: self.text(doc, "uri").unwrap_or_default(),
+            title: StoredDocs::first_text(doc, &self.titles).unwrap_or_default(),
+            body: self.body(doc)?,
+            date,
+            record_id: self.text(doc, "record_id"),
+            language: self.text(doc, "language"),
+            content_length: self.number(doc, "content_length"),
+            host: self.text(doc, "host"),
+            scheme: self.text(doc, "scheme"),
+            domain: self.text(doc, "domain"),
+            tld: self.text(doc, "tld"),
+            content_hash: self.text(doc, "content_hash"),
+            block_digest: self.text(doc, "block_digest"),
+            simhash: self.number(doc, "simhash"),
+            duplicate_of: self.number(doc, "duplicate_of"),
+            source_file: self.text(doc, "source_file"),
+            source_offset: self.number(doc, "source_offset"),
+            embedding: None,
+            parent_id: self.text(doc, "parent_id"),
+            passage: self.number(doc, "passage"),
+            passage_start: self.number(doc, "passage_start"),
+            passage_end: self.number(doc, "passage_end"),
+        })
+    }
+}
+
+// Shards built with `--store-body truncate` or `none` only keep part or none
+// of their bodies. Reading them back from the input files would need those
+// files at the paths of the machine that built the shard, which the machine
+// combining them rarely has, so only full and zstd bodies are combined.
+fn stores_bodies(schema: &Schema) -> bool {
+    let full = schema
+        .get_field("body")
+        .map_or(false, |body| schema.get_field_entry(body).is_stored());
+    full || schema.get_field("body_zstd").is_some()
+}
+
+fn open(dir: &Path) -> io::Result<Index> {
+    let index = Index::open_in_dir(dir).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: {:?}", dir.to_string_lossy(), e),
+        )
+    })?;
+    tokenizers::register(&index);
+    Ok(index)
+}
+
+// `combine` subcommand: indexes the documents of the `shards`, indexes with
+// identical schemas, into a new index in `dir`. Of documents with the same id,
+// their `record_id` or else their `uri`, only the first is kept.
+pub fn run(dir: &Path, shards: &[&Path], threads: usize, heap_size: usize) -> io::Result<()> {
+    if dir.join("meta.json").exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already holds an index", dir.to_string_lossy()),
+        ));
+    }
+    let shard_indexes = shards
+        .iter()
+        .map(|shard| open(shard))
+        .collect::<io::Result<Vec<Index>>>()?;
+    let expected = match shard_indexes.first() {
+        Some(index) => index.schema(),
+        None => return Ok(()),
+    };
+    // checked both ways, as `mismatches` allows additional fields
+    for (shard, index) in shards.iter().zip(&shard_indexes) {
+        let mut report = schema::mismatches(&index.schema(), &expected);
+        report.extend(schema::mismatches(&expected, &index.schema()));
+        if !report.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "schema of {} differs from {}:\n  {}",
+                    shard.to_string_lossy(),
+                    shards[0].to_string_lossy(),
+                    report.join("\n  ")
+                ),
+            ));
+        }
+    }
+
+    if !stores_bodies(&expected) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} does not store its bodies, combine needs shards built with --store-body full or zstd",
+                shards[0].to_string_lossy()
+            ),
+        ));
+    }
+
+    let index = schema::open_or_create_index(dir, &expected)?;
+    tokenizers::register(&index);
+    let fields = DocFields::resolve(&expected)?;
+    let stored = StoredDocs::new(&expected);
+    let mut writer = index
+        .writer_with_num_threads(threads, heap_size)
+        .map_err(to_io_error)?;
+    let mut seen: HashSet<String> = HashSet::new();
+    for (shard, shard_index) in shards.iter().zip(&shard_indexes) {
+        let searcher = shard_index.reader().map_err(to_io_error)?.searcher();
+        let (mut added, mut duplicates) = (0, 0);
+        for (segment_ord, segment) in searcher.segment_readers().iter().enumerate() {
+            for doc_id in 0..segment.max_doc() {
+                if segment.is_deleted(doc_id) {
+                    continue;
+                }
+                let address = DocAddress {
+                    segment_ord: segment_ord as u32,
+                    doc_id,
+                };
+                let doc = stored.doc_json(&searcher.doc(address).map_err(to_io_error)?)?;
+                let id = doc.record_id.clone().unwrap_or_else(|| doc.uri.clone());
+                if !seen.insert(id) {
+                    duplicates += 1;
+                    continue;
+                }
+                writer.add_document(fields.document(&doc));
+                added += 1;
+            }
+        }
+        writer.commit().map_err(to_io_error)?;
+        println!(
+            "{}: {} documents added, {} duplicates",
+            shard.to_string_lossy(),
+            added,
+            duplicates
+        );
+    }
+    writer.wait_merging_threads().map_err(to_io_error)
+}
@@ -0,0 +1,452 @@
+use std;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use docopt::ArgvMap;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::chunk::Chunker;
+use crate::embed::Embedding;
+use crate::exact_dup::SeenSet;
+use crate::index_sink::IndexTarget;
+use crate::lang;
+use crate::merge::MergePolicyOption;
+use crate::near_dup::{NearDup, NearDupMode};
+use crate::pipeline::Pipeline;
+use crate::quality::QualityFilter;
+use crate::schema::{BodyStore, SchemaOptions, SourceType};
+use crate::sink::{OutputCompression, OutputFormat, OutputOptions};
+use crate::url_filter::UrlFilter;
+
+// A complete indexing job as read from `--config job.toml`. Every option
+// given on the command line overrides the value of the file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    // WARC, ENTREZ or WIKIPEDIA_ABSTRACT, `-s`
+    pub source: String,
+    // directory of the input files, <warc_dir>
+    pub inputs: String,
+    // output directory or index, <index>
+    pub index: String,
+    // only process the input files from..=to, counted from 1
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    // only process the input files of this shard, `<i>/<n>`, see `Shard`
+    pub shard: Option<String>,
+    pub sink: SinkConfig,
+    pub schema: SchemaConfig,
+    pub filters: FilterConfig,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinkConfig {
+    pub format: String,
+    pub compress: String,
+    pub max_docs: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub row_group_size: Option<usize>,
+    pub upsert: bool,
+    // log, optionally with settings, or none
+    pub merge_policy: String,
+    // `hash` or an embedding server's URL, optionally with settings
+    pub embed: Option<String>,
+    // where `format = "index"` writes embeddings, default `<index>.embeddings`
+    pub embeddings_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemaConfig {
+    pub multilingual: bool,
+    // full, zstd, truncate=<chars> or none
+    pub store_body: String,
+    // words, sentences or paragraphs, optionally with a size and overlap
+    pub chunk: Option<String>,
+    pub sentence_positions: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub lang: Vec<String>,
+    pub url_rules: Option<String>,
+    pub block_hosts: Option<String>,
+    pub quality: Vec<String>,
+    pub bad_words: Option<String>,
+    pub dedup: Option<String>,
+    pub near_dup: Option<String>,
+    pub near_dup_bits: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    // threads of the index writer
+    pub threads: usize,
+    // input files processed at the same time
+    pub tasks: usize,
+    // read buffer per input file, in bytes
+    pub read_buffer: usize,
+    // memory of the index writer, in bytes
+    pub writer_heap: usize,
+    // documents handed to the sink at once, 10 for WARC and 1000 otherwise
+    pub batch_size: Option<usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            threads: 4,
+            tasks: 16,
+            read_buffer: 600 * 1024 * 1024,
+            writer_heap: 1024 * 1024 * 1024,
+            batch_size: None,
+        }
+    }
+}
+
+// One of `count` disjoint parts of the input files, for building an index
+// per worker that are put together with `combine`. Files are assigned by a
+// hash of their name, so every worker picks the same files regardless of the
+// order it lists them in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    // Parses `<i>/<n>` with i counted from 0, e.g. `2/8`.
+    pub fn parse(shard: &str) -> Option<Shard> {
+        let (index, count) = shard.split_once('/')?;
+        let index = index.trim().parse::<u64>().ok()?;
+        let count = count.trim().parse::<u64>().ok()?;
+        Some(Shard { index, count }).filter(|_| index < count)
+    }
+
+    pub fn contains(&self, file_name: &str) -> bool {
+        xxh3_64(file_name.as_bytes()) % self.count == self.index
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl JobConfig {
+    pub fn load(path: &Path) -> io::Result<JobConfig> {
+        toml::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.to_string_lossy(), e),
+            )
+        })
+    }
+
+    // Overrides the values of the file with the options given in `args`.
+    // Returns an error for every option that needs a number but got none,
+    // leaving the value of the file in place.
+    pub fn apply_args(&mut self, args: &ArgvMap) -> Vec<String> {
+        let text = |name: &str| Some(args.get_str(name)).filter(|value| !value.is_empty());
+        let path = |name: &str| text(name).map(|value| value.to_string());
+        let mut errors = Vec::new();
+        let mut number = |name: &str| {
+            let value = text(name)?;
+            match value.parse::<usize>() {
+                Ok(number) => Some(number),
+                Err(_) => {
+                    errors.push(format!("{} {} is not a number", name, value));
+                    None
+                }
+            }
+        };
+
+        if let Some(source) = path("-s") {
+            self.source = source;
+        }
+        if let Some(inputs) = path("<warc_dir>") {
+            self.inputs = inputs;
+        }
+        if let Some(index) = path("<index>") {
+            self.index = index;
+        }
+        self.from = number("--from").or(self.from);
+        self.to = number("--to").or(self.to);
+        self.shard = path("--shard").or(self.shard.take());
+
+        if let Some(format) = path("--format") {
+            self.sink.format = format;
+        }
+        if let Some(compress) = path("--compress") {
+            self.sink.compress = compress;
+        }
+        self.sink.max_docs = number("--max-docs").or(self.sink.max_docs);
+        self.sink.max_bytes = number("--max-bytes")
+            .map(|bytes| bytes as u64)
+            .or(self.sink.max_bytes);
+        self.sink.row_group_size = number("--row-group-size").or(self.sink.row_group_size);
+        self.sink.upsert |= args.get_bool("--upsert");
+        if let Some(merge_policy) = path("--merge-policy") {
+            self.sink.merge_policy = merge_policy;
+        }
+        self.sink.embed = path("--embed").or(self.sink.embed.take());
+        self.sink.embeddings_dir = path("--embeddings-dir").or(self.sink.embeddings_dir.take());
+        self.schema.multilingual |= args.get_bool("--multilingual");
+        self.schema.sentence_positions |= args.get_bool("--sentence-positions");
+        if let Some(store_body) = path("--store-body") {
+            self.schema.store_body = store_body;
+        }
+        self.schema.chunk = path("--chunk").or(self.schema.chunk.take());
+
+        if let Some(lang) = text("--lang") {
+            self.filters.lang = split_list(lang);
+        }
+        self.filters.url_rules = path("--url-rules").or(self.filters.url_rules.take());
+        self.filters.block_hosts = path("--block-hosts").or(self.filters.block_hosts.take());
+        if let Some(quality) = text("--quality") {
+            self.filters.quality = split_list(quality);
+        }
+        self.filters.bad_words = path("--bad-words").or(self.filters.bad_words.take());
+        self.filters.dedup = path("--dedup").or(self.filters.dedup.take());
+        self.filters.near_dup = path("--near-dup").or(self.filters.near_dup.take());
+        self.filters.near_dup_bits = number("--near-dup-bits")
+            .map(|bits| u32::try_from(bits).unwrap_or(u32::MAX))
+            .or(self.filters.near_dup_bits);
+
+        if let Some(threads) = number("-t") {
+            self.limits.threads = threads;
+        }
+        if let Some(tasks) = number("--tasks") {
+            self.limits.tasks = tasks;
+        }
+        self.limits.batch_size = number("--batch-size").or(self.limits.batch_size);
+        errors
+    }
+
+    // Everything wrong with the job, so it fails on startup rather than
+    // after hours of indexing.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if SourceType::parse(&self.source).is_none() {
+            errors.push(format!("unknown source type '{}'", self.source));
+        }
+        if self.inputs.is_empty() {
+            errors.push("no input directory".to_string());
+        } else if !Path::new(&self.inputs).is_dir() {
+            errors.push(format!("input directory {} does not exist", self.inputs));
+        }
+        if self.index.is_empty() {
+            errors.push("no output directory or index".to_string());
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                errors.push(format!("from {} is after to {}", from, to));
+            }
+        }
+        if let Some(shard) = &self.shard {
+            if Shard::parse(shard).is_none() {
+                errors.push(format!("shard {} is not <i>/<n> with i < n", shard));
+            }
+        }
+
+        if OutputFormat::parse(&self.sink.format).is_none() {
+            errors.push(format!("unknown output format {}", self.sink.format));
+        }
+        // one parquet file per input, the limits would be silently ignored
+        if OutputFormat::parse(&self.sink.format) == Some(OutputFormat::Parquet)
+            && (self.sink.max_docs.is_some() || self.sink.max_bytes.is_some())
+        {
+            errors.push("max_docs and max_bytes only split ndjson output, not parquet".to_string());
+        }
+        if OutputCompression::parse(&self.sink.compress).is_none() {
+            errors.push(format!("unknown output compression {}", self.sink.compress));
+        }
+        if let Err(e) = MergePolicyOption::parse(&self.sink.merge_policy) {
+            errors.push(e);
+        }
+        if let Some(Err(e)) = self.sink.embed.as_deref().map(Embedding::parse) {
+            errors.push(e);
+        }
+        if let Some(dir) = &self.sink.embeddings_dir {
+            if OutputFormat::parse(&self.sink.format) != Some(OutputFormat::Index) {
+                errors.push("embeddings_dir is only used by format index".to_string());
+            } else if !self.index.is_empty() && Path::new(dir).starts_with(&self.index) {
+                errors.push(format!("embeddings_dir {} is inside the index", dir));
+            }
+        }
+        if BodyStore::parse(&self.schema.store_body).is_none() {
+            errors.push(format!("unknown body store {}", self.schema.store_body));
+        }
+        if let Some(Err(e)) = self.schema.chunk.as_deref().map(Chunker::parse) {
+            errors.push(e);
+        }
+        let positive = [
+            ("max_docs", self.sink.max_docs),
+            ("max_bytes", self.sink.max_bytes.map(|bytes| bytes as usize)),
+            ("row_group_size", self.sink.row_group_size),
+            ("threads", Some(self.limits.threads)),
+            ("tasks", Some(self.limits.tasks)),
+            ("read_buffer", Some(self.limits.read_buffer)),
+            ("writer_heap", Some(self.limits.writer_heap)),
+            ("batch_size", self.limits.batch_size),
+        ];
+        for (name, value) in positive.iter() {
+            if *value == Some(0) {
+                errors.push(format!("{} must be a number greater than 0", name));
+            }
+        }
+
+        for code in &self.filters.lang {
+            if !lang::is_known_code(code) {
+                errors.push(format!("unknown ISO 639-3 language code {}", code));
+            }
+        }
+        for rule in &self.filters.quality {
+            if let Err(e) = QualityFilter::parse(rule) {
+                errors.push(e);
+            }
+        }
+        let files = [
+            ("url_rules", &self.filters.url_rules),
+            ("block_hosts", &self.filters.block_hosts),
+            ("bad_words", &self.filters.bad_words),
+        ];
+        for (name, file) in files.iter() {
+            if let Some(file) = file {
+                if !Path::new(file).is_file() {
+                    errors.push(format!("{} file {} does not exist", name, file));
+                }
+            }
+        }
+        if let Some(mode) = &self.filters.near_dup {
+            if NearDupMode::parse(mode).is_none() {
+                errors.push(format!("unknown near duplicate mode {}", mode));
+            }
+        }
+        // more bits than a SimHash has make every document a near duplicate
+        if let Some(bits) = self.filters.near_dup_bits {
+            if bits > 64 {
+                errors.push(format!("near_dup_bits {} is more than 64", bits));
+            }
+        }
+        errors
+    }
+
+    // Only valid after `validate` returned no errors.
+    pub fn source_type(&self) -> SourceType {
+        SourceType::parse(&self.source).expect("validated source type")
+    }
+
+    pub fn shard(&self) -> Option<Shard> {
+        self.shard
+            .as_ref()
+            .map(|shard| Shard::parse(shard).expect("validated shard"))
+    }
+
+    // The output options, opening or creating the index for `--format index`.
+    pub fn output_options(&self) -> io::Result<OutputOptions> {
+        let mut output = OutputOptions {
+            format: OutputFormat::parse(&self.sink.format).expect("validated format"),
+            compression: OutputCompression::parse(&self.sink.compress)
+                .expect("validated compression"),
+            max_docs: self.sink.max_docs,
+            max_bytes: self.sink.max_bytes,
+            row_group_size: self
+                .sink
+                .row_group_size
+                .unwrap_or(OutputOptions::default().row_group_size),
+            batch_size: self.limits.batch_size,
+            index: None,
+            embeddings_dir: self.sink.embeddings_dir.as_ref().map(PathBuf::from),
+        };
+        if output.format == OutputFormat::Index {
+            let schema = SchemaOptions {
+                multilingual: self.schema.multilingual,
+                body_store: BodyStore::parse(&self.schema.store_body)
+                    .expect("validated body store"),
+                passages: self.schema.chunk.is_some(),
+                sentence_positions: self.schema.sentence_positions,
+            };
+            let target = IndexTarget::open(
+                Path::new(&self.index),
+                self.source_type(),
+                schema,
+                self.limits.threads,
+                self.limits.writer_heap,
+                self.sink.upsert,
+            )?;
+            let merge_policy =
+                MergePolicyOption::parse(&self.sink.merge_policy).expect("validated merge policy");
+            target.set_merge_policy(merge_policy.policy());
+            output.index = Some(Arc::new(target));
+        }
+        Ok(output)
+    }
+
+    // The pipeline with all filters, reading their rule files.
+    pub fn pipeline(&self) -> io::Result<Pipeline> {
+        let filters = &self.filters;
+        let mut pipeline = Pipeline::default();
+        if !filters.lang.is_empty() {
+            let languages: HashSet<String> = filters
+                .lang
+                .iter()
+                .map(|code| lang::normalize(code))
+                .collect();
+            pipeline.languages = Some(Arc::new(languages));
+        }
+
+        let mut urls = UrlFilter::default();
+        if let Some(rules) = &filters.url_rules {
+            urls.add_rules(Path::new(rules))?;
+        }
+        if let Some(hosts) = &filters.block_hosts {
+            urls.add_blocklist(Path::new(hosts))?;
+        }
+        if !urls.is_empty() {
+            pipeline.urls = Some(Arc::new(urls));
+        }
+
+        let mut quality = filters
+            .quality
+            .iter()
+            .map(|rule| QualityFilter::parse(rule).expect("validated quality rule"))
+            .collect::<Vec<QualityFilter>>();
+        if let Some(bad_words) = &filters.bad_words {
+            quality.push(QualityFilter::bad_words(Path::new(bad_words))?);
+        }
+        if !quality.is_empty() {
+            pipeline.quality = Some(Arc::new(quality));
+        }
+
+        if let Some(dedup) = &filters.dedup {
+            pipeline.seen = Some(Arc::new(SeenSet::open(Path::new(dedup))?));
+        }
+        if let Some(mode) = &filters.near_dup {
+            let mode = NearDupMode::parse(mode).expect("validated near duplicate mode");
+            let max_distance = filters.near_dup_bits.unwrap_or(3);
+            pipeline.near_dup = Some(Arc::new(NearDup::new(mode, max_distance)));
+        }
+        if let Some(chunk) = &self.schema.chunk {
+            let chunker = Chunker::parse(chunk).expect("validated chunker");
+            pipeline.chunker = Some(Arc::new(chunker));
+        }
+        if let Some(embed) = &self.sink.embed {
+            let embedding = Embedding::parse(embed).expect("validated embedder");
+            pipeline.embedding = Some(Arc::new(embedding));
+        }
+        Ok(pipeline)
+    }
+}
@@ -0,0 +1,209 @@
+use std;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// All dates are normalized to RFC 3339 in UTC, e.g. `2020-04-10T14:25:56Z`,
+// which is what `DocJson::date` holds and the index's date field parses.
+pub fn format(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+pub fn parse(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// WARC-Date is a W3C-ISO8601 date, possibly with fractional seconds or
+// reduced to minute or day precision.
+pub fn from_warc_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    if let Some(date) = parse(value) {
+        return Some(format(date));
+    }
+    if let Ok(date) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%MZ") {
+        return Some(format(Utc.from_utc_datetime(&date)));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(0, 0, 0)?;
+    Some(format(Utc.from_utc_datetime(&date)))
+}
+
+// The month of a PubMed `<Month>`, `Jan` or `01`, or of a `<Season>`, which
+// stands for its first month, winter for January of its year.
+fn pubmed_month(month: &str) -> Option<u32> {
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let seasons = [
+        ("winter", 1),
+        ("spring", 3),
+        ("summer", 6),
+        ("fall", 9),
+        ("autumn", 9),
+    ];
+    if let Ok(month) = month.parse::<u32>() {
+        return Some(month).filter(|month| (1..=12).contains(month));
+    }
+    let name = month.to_lowercase();
+    if let Some((_, month)) = seasons.iter().find(|(season, _)| *season == name) {
+        return Some(*month);
+    }
+    let prefix = name.get(..3)?;
+    months
+        .iter()
+        .position(|name| *name == prefix)
+        .map(|i| i as u32 + 1)
+}
+
+// PubMed's <PubDate> has a year and an optional month, either `Jan` or `01`
+// or a season, and day. Missing parts default to the first month or day.
+pub fn from_pubmed_date(
+    year: Option<&str>,
+    month: Option<&str>,
+    day: Option<&str>,
+) -> Option<String> {
+    let year = year?.trim().parse::<i32>().ok()?;
+    let month = match month.map(|month| month.trim()) {
+        None | Some("") => 1,
+        Some(month) => pubmed_month(month)?,
+    };
+    let day = day
+        .and_then(|day| day.trim().parse::<u32>().ok())
+        .unwrap_or(1);
+    let date = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(0, 0, 0)?;
+    Some(format(Utc.from_utc_datetime(&date)))
+}
+
+// A <MedlineDate> is free text for dates not fitting <PubDate>, mostly a
+// year followed by a month, a season or a range, e.g. `1998 Dec-1999 Jan`
+// or `2000 Spring`. The start of the range is taken.
+pub fn from_medline_date(value: &str) -> Option<String> {
+    let mut words = value.split(|c: char| c.is_whitespace() || c == '-');
+    let year = words.next()?;
+    let month = words.next().filter(|month| pubmed_month(month).is_some());
+    from_pubmed_date(Some(year), month, None)
+}
+
+// Wikipedia dumps are named like `enwiki-20240601-abstract.xml.gz`, the date
+// of the dump is the date of all its pages.
+pub fn from_wikipedia_dump_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    name.split(|c| c == '-' || c == '.')
+        .filter(|part| part.len() == 8 && part.chars().all(|c| c.is_ascii_digit()))
+        .find_map(|part| NaiveDate::parse_from_str(part, "%Y%m%d").ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| format(Utc.from_utc_datetime(&date)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pubmed_dates() {
+        let cases = [
+            (
+                (Some("2020"), Some("Mar"), Some("5")),
+                Some("2020-03-05T00:00:00Z"),
+            ),
+            (
+                (Some("2020"), Some("03"), None),
+                Some("2020-03-01T00:00:00Z"),
+            ),
+            (
+                (Some("2020"), Some("September"), None),
+                Some("2020-09-01T00:00:00Z"),
+            ),
+            (
+                (Some(" 2020 "), Some(""), Some("")),
+                Some("2020-01-01T00:00:00Z"),
+            ),
+            ((Some("2020"), None, None), Some("2020-01-01T00:00:00Z")),
+            (
+                (Some("2001"), Some("Summer"), None),
+                Some("2001-06-01T00:00:00Z"),
+            ),
+            (
+                (Some("2001"), Some("Winter"), None),
+                Some("2001-01-01T00:00:00Z"),
+            ),
+            (
+                (Some("2001"), Some("Fall"), None),
+                Some("2001-09-01T00:00:00Z"),
+            ),
+            ((None, Some("Mar"), None), None),
+            ((Some("n.d."), None, None), None),
+            ((Some("2020"), Some("Smarch"), None), None),
+            ((Some("2020"), Some("13"), None), None),
+            ((Some("2021"), Some("Feb"), Some("30")), None),
+        ];
+        for ((year, month, day), expected) in cases.iter() {
+            assert_eq!(
+                from_pubmed_date(*year, *month, *day).as_deref(),
+                *expected,
+                "{:?} {:?} {:?}",
+                year,
+                month,
+                day
+            );
+        }
+    }
+
+    #[test]
+    fn medline_dates() {
+        let cases = [
+            ("1998 Dec-1999 Jan", Some("1998-12-01T00:00:00Z")),
+            ("2000 Spring", Some("2000-03-01T00:00:00Z")),
+            ("2000 Nov-Dec", Some("2000-11-01T00:00:00Z")),
+            ("1999-2000", Some("1999-01-01T00:00:00Z")),
+            ("2002 1st Quarter", Some("2002-01-01T00:00:00Z")),
+            ("Spring 2000", None),
+            ("", None),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(from_medline_date(value).as_deref(), *expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn warc_dates() {
+        let cases = [
+            ("2020-04-10T14:25:56Z", Some("2020-04-10T14:25:56Z")),
+            ("2020-04-10T14:25:56.123Z", Some("2020-04-10T14:25:56Z")),
+            ("2020-04-10T16:25:56+02:00", Some("2020-04-10T14:25:56Z")),
+            (" 2020-04-10T14:25Z ", Some("2020-04-10T14:25:00Z")),
+            ("2020-04-10", Some("2020-04-10T00:00:00Z")),
+            ("2020-04", None),
+            ("10/04/2020", None),
+            ("2020-02-30", None),
+            ("", None),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(from_warc_date(value).as_deref(), *expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn wikipedia_dump_names() {
+        let cases = [
+            (
+                "enwiki-20240601-abstract.xml.gz",
+                Some("2024-06-01T00:00:00Z"),
+            ),
+            (
+                "/dumps/dewiki-20231120-abstract1.xml",
+                Some("2023-11-20T00:00:00Z"),
+            ),
+            ("enwiki-latest-abstract.xml.gz", None),
+            ("enwiki-20241399-abstract.xml", None),
+            ("enwiki-2024060-abstract.xml", None),
+        ];
+        for (name, expected) in cases.iter() {
+            let date = from_wikipedia_dump_name(Path::new(name));
+            assert_eq!(date.as_deref(), *expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+// Bytes at the start of an HTML page searched for a `<meta>` charset, 4 KiB
+// as pages declare it after long `<head>` scripts at times.
+const META_PREFIX: usize = 4096;
+// Times mojibake is undone, text is at times mis-decoded more than once.
+const MAX_MOJIBAKE_ROUNDS: usize = 3;
+
+// How bytes were turned into text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decoding {
+    pub encoding: &'static Encoding,
+    // invalid byte sequences were replaced by U+FFFD
+    pub lossy: bool,
+}
+
+// What `repair` changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Repairs {
+    pub mojibake: bool,
+    pub normalized: bool,
+}
+
+impl Repairs {
+    pub fn any(&self) -> bool {
+        self.mojibake || self.normalized
+    }
+
+    pub fn or(self, other: Repairs) -> Repairs {
+        Repairs {
+            mojibake: self.mojibake || other.mojibake,
+            normalized: self.normalized || other.normalized,
+        }
+    }
+}
+
+// The `charset` parameter of an HTTP `Content-Type` value.
+pub fn content_type_charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches(|c| c == '"' || c == '\''))
+        } else {
+            None
+        }
+    })
+}
+
+// The charset of `<meta charset="...">` or `<meta http-equiv="Content-Type"
+// content="text/html; charset=...">` near the start of `html`.
+pub fn meta_charset(html: &[u8]) -> Option<String> {
+    let prefix = &html[..html.len().min(META_PREFIX)];
+    let prefix = String::from_utf8_lossy(prefix).to_ascii_lowercase();
+    for (start, _) in prefix.match_indices("<meta") {
+        let tag = &prefix[start..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        if let Some(i) = tag.find("charset=") {
+            let value = tag[i + "charset=".len()..].trim_start_matches(|c| c == '"' || c == '\'');
+            let end = value
+                .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+                .unwrap_or(value.len());
+            if end > 0 {
+                return Some(value[..end].to_string());
+            }
+        }
+    }
+    None
+}
+
+// The `encoding` of the declaration `<?xml version="1.0" encoding="...">`
+// that `xml` starts with.
+pub fn xml_charset(xml: &[u8]) -> Option<String> {
+    let prefix = &xml[..xml.len().min(256)];
+    let prefix = String::from_utf8_lossy(prefix);
+    let declaration = prefix
+        .trim_start_matches('\u{feff}')
+        .strip_prefix("<?xml")?;
+    let declaration = &declaration[..declaration.find("?>")?];
+    let value = declaration.split("encoding=").nth(1)?;
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+// Decodes `bytes` in the encoding labeled `charset`, UTF-8 if there is none
+// or it is unknown. Invalid sequences become U+FFFD instead of failing.
+pub fn decode(bytes: &[u8], charset: Option<&str>) -> (String, Decoding) {
+    let encoding = charset
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    if encoding == UTF_8 {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            let decoding = Decoding {
+                encoding,
+                lossy: false,
+            };
+            return (text.to_string(), decoding);
+        }
+    }
+    let (text, lossy) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), Decoding { encoding, lossy })
+}
+
+// Undoes UTF-8 text having been decoded as windows-1252 or Latin-1, e.g.
+// `cafÃ©` for `café` or `â€œ` for `“`: the text is taken back to those bytes,
+// which must then be valid UTF-8. Text with characters windows-1252 cannot
+// encode is left alone, so only wholly mis-decoded text is repaired.
+pub fn undo_mojibake(text: &str) -> Option<String> {
+    // every mis-decoded multi-byte character has one of these lead bytes
+    if !text.chars().any(|c| ('\u{c2}'..='\u{f4}').contains(&c)) {
+        return None;
+    }
+    let (bytes, _, unmappable) = WINDOWS_1252.encode(text);
+    if unmappable {
+        return None;
+    }
+    match String::from_utf8(bytes.into_owned()) {
+        Ok(repaired) if repaired != text => Some(repaired),
+        _ => None,
+    }
+}
+
+// Repairs mojibake in `text` and normalizes it to NFC, the form most text
+// on the web is in, so that equal text is equal bytes.
+pub fn repair(text: &mut String) -> Repairs {
+    let mut repairs = Repairs::default();
+    if text.is_ascii() {
+        return repairs;
+    }
+    for _ in 0..MAX_MOJIBAKE_ROUNDS {
+        match undo_mojibake(text) {
+            Some(repaired) => {
+                *text = repaired;
+                repairs.mojibake = true;
+            }
+            None => break,
+        }
+    }
+    if is_nfc_quick(text.chars()) != IsNormalized::Yes {
+        let normalized: String = text.nfc().collect();
+        if normalized != *text {
+            *text = normalized;
+            repairs.normalized = true;
+        }
+    }
+    repairs
+}
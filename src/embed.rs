@@ -0,0 +1,230 @@
+use std;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::warc::DocJson;
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// Characters of a document's title and body that are embedded, more would
+// be cut off by most models anyway.
+const DEFAULT_MAX_CHARS: usize = 2000;
+const DEFAULT_HASH_DIMENSIONS: usize = 256;
+
+// Turns texts into dense vectors, one per text and all of the same length.
+pub trait Embedder: fmt::Debug + Send + Sync {
+    fn embed(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>>;
+}
+
+// Feature hashing of the lower cased words and word pairs, L2 normalized.
+// Needs no model and is deterministic, but only captures shared vocabulary;
+// it is meant as a baseline and for testing the rest of a setup.
+#[derive(Debug)]
+pub struct HashEmbedder {
+    pub dimensions: usize,
+}
+
+impl HashEmbedder {
+    fn add(&self, vector: &mut [f32], feature: &str) {
+        let hash = xxh3_64(feature.as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % self.dimensions as u64) as usize] += sign;
+    }
+}
+
+impl Embedder for HashEmbedder {
+    fn embed(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>> {
+        let embeddings = texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0f32; self.dimensions];
+                let words: Vec<String> = text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|word| !word.is_empty())
+                    .map(|word| word.to_lowercase())
+                    .collect();
+                for word in &words {
+                    self.add(&mut vector, word);
+                }
+                for pair in words.windows(2) {
+                    self.add(&mut vector, &format!("{} {}", pair[0], pair[1]));
+                }
+                let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    vector.iter_mut().for_each(|x| *x /= norm);
+                }
+                vector
+            })
+            .collect();
+        Ok(embeddings)
+    }
+}
+
+// A local embedding server. URLs ending in `/embed` are called like
+// text-embeddings-inference, `{"inputs": [...]}` answered by a list of
+// vectors, all others like the OpenAI embeddings API, `{"input": [...],
+// "model": ...}` answered by `{"data": [{"embedding": [...]}, ...]}`.
+#[derive(Debug)]
+pub struct HttpEmbedder {
+    pub url: String,
+    pub model: Option<String>,
+    // created on the first request, outside of tokio, and shared by all
+    // later ones so they reuse its connections
+    client: Mutex<Option<reqwest::blocking::Client>>,
+}
+
+fn vector(value: &Value) -> Option<Vec<f32>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect()
+}
+
+impl HttpEmbedder {
+    pub fn new(url: String, model: Option<String>) -> HttpEmbedder {
+        HttpEmbedder {
+            url,
+            model,
+            client: Mutex::new(None),
+        }
+    }
+
+    // A handle to the shared client, which is reference counted.
+    fn client(&self) -> reqwest::blocking::Client {
+        let mut client = self.client.lock().unwrap();
+        client
+            .get_or_insert_with(reqwest::blocking::Client::new)
+            .clone()
+    }
+
+    fn request(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>> {
+        let body = if self.url.ends_with("/embed") {
+            json!({ "inputs": texts })
+        } else {
+            json!({ "input": texts, "model": self.model })
+        };
+        let response: Value = self
+            .client()
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .map_err(to_io_error)?;
+        let vectors = match response.get("data") {
+            Some(data) => data.as_array().map(|data| {
+                data.iter()
+                    .map(|item| item.get("embedding").and_then(vector))
+                    .collect::<Option<Vec<Vec<f32>>>>()
+            }),
+            None => response
+                .as_array()
+                .map(|vectors| vectors.iter().map(vector).collect()),
+        };
+        match vectors.flatten() {
+            Some(vectors) if vectors.len() == texts.len() => Ok(vectors),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} did not answer with {} vectors", self.url, texts.len()),
+            )),
+        }
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    // The blocking client must not be created or run on a tokio worker, so
+    // every request gets a thread of its own. Batches make that cheap per
+    // document.
+    fn embed(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>> {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| self.request(texts))
+                .join()
+                .unwrap_or_else(|_| Err(to_io_error("embedding request panicked")))
+        })
+    }
+}
+
+// The embedding stage of the pipeline, `--embed`.
+#[derive(Debug)]
+pub struct Embedding {
+    pub embedder: Box<dyn Embedder>,
+    pub max_chars: usize,
+}
+
+impl Embedding {
+    // Parses `hash` or the URL of an embedding server, followed by comma
+    // separated settings, e.g. `hash,dims=384` or
+    // `http://localhost:8080/v1/embeddings,model=bge-small,max-chars=1000`.
+    pub fn parse(spec: &str) -> Result<Embedding, String> {
+        let mut parts = spec.split(',').map(|part| part.trim());
+        let kind = parts.next().unwrap_or_default();
+        let (mut dimensions, mut model, mut max_chars) = (None, None, DEFAULT_MAX_CHARS);
+        for setting in parts {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("embedding setting {} has no value", setting))?;
+            let count = || {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("embedding setting {} needs a count", name))
+            };
+            match name {
+                "dims" => dimensions = Some(count()?),
+                "max-chars" => max_chars = count()?,
+                "model" => model = Some(value.to_string()),
+                _ => return Err(format!("unknown embedding setting {}", name)),
+            }
+        }
+        let embedder: Box<dyn Embedder> = if kind == "hash" {
+            if model.is_some() {
+                return Err("the hash embedder has no model".to_string());
+            }
+            Box::new(HashEmbedder {
+                dimensions: dimensions.unwrap_or(DEFAULT_HASH_DIMENSIONS),
+            })
+        } else if kind.starts_with("http://") || kind.starts_with("https://") {
+            if dimensions.is_some() {
+                return Err("the dimensions of a server's vectors are set by its model".to_string());
+            }
+            Box::new(HttpEmbedder::new(kind.to_string(), model))
+        } else {
+            return Err(format!("unknown embedder {}", kind));
+        };
+        Ok(Embedding {
+            embedder,
+            max_chars,
+        })
+    }
+
+    // The text embedded for `doc`, its title and the start of its body.
+    fn text(&self, doc: &DocJson) -> String {
+        let text = format!("{}\n{}", doc.title, doc.body);
+        match text.char_indices().nth(self.max_chars) {
+            Some((end, _)) => text[..end].to_string(),
+            None => text,
+        }
+    }
+
+    // Sets the `embedding` of every document of `batch`.
+    pub fn embed(&self, batch: &mut [DocJson]) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let texts: Vec<String> = batch.iter().map(|doc| self.text(doc)).collect();
+        let vectors = self.embedder.embed(&texts)?;
+        for (doc, vector) in batch.iter_mut().zip(vectors) {
+            doc.embedding = Some(vector);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use xxhash_rust::xxh3::xxh3_128;
+
+// The exact content hash of a body, its XXH3-128.
+pub fn content_hash(body: &str) -> u128 {
+    xxh3_128(body.as_bytes())
+}
+
+// `content_hash` as stored in documents, 32 hex digits.
+pub fn to_hex(hash: u128) -> String {
+    format!("{:032x}", hash)
+}
+
+// One record of the seen-set file: the hash and the id of the input it was
+// first seen in, see `SeenSet::input_id`.
+const RECORD_LEN: usize = 20;
+
+struct SeenState {
+    // input file names, the line number in `<file>.inputs` is the id
+    inputs: HashMap<String, u32>,
+    inputs_log: File,
+    // hashes of earlier runs sharing the seen-set and their input
+    previous: HashMap<u128, u32>,
+    current: HashSet<u128>,
+    log: BufWriter<File>,
+}
+
+// The content hashes of all documents kept so far, shared by all tasks of a
+// run and, through the seen-set file, with later runs.
+//
+// A document is a duplicate if its hash was seen earlier in this run or in an
+// earlier run from a different input file. Hashes of an earlier run from the
+// same input file don't count, so re-running over a file that was done or
+// interrupted writes the same documents again.
+pub struct SeenSet {
+    path: PathBuf,
+    state: Mutex<SeenState>,
+}
+
+impl std::fmt::Debug for SeenSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SeenSet({})", self.path.to_string_lossy())
+    }
+}
+
+impl SeenSet {
+    // Opens the seen-set in `path` and `<path>.inputs`, creating both if needed.
+    pub fn open(path: &Path) -> io::Result<SeenSet> {
+        let mut inputs_path = path.as_os_str().to_owned();
+        inputs_path.push(".inputs");
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(path)
+        };
+
+        let mut inputs = HashMap::new();
+        let inputs_log = open(Path::new(&inputs_path))?;
+        for line in BufReader::new(&inputs_log).lines() {
+            let id = inputs.len() as u32;
+            inputs.insert(line?, id);
+        }
+
+        let mut previous = HashMap::new();
+        let log = open(path)?;
+        // drop a partial last record of an interrupted run
+        let len = log.metadata()?.len();
+        log.set_len(len - len % RECORD_LEN as u64)?;
+        let mut reader = BufReader::new(&log);
+        let mut record = [0u8; RECORD_LEN];
+        for _ in 0..len / RECORD_LEN as u64 {
+            reader.read_exact(&mut record)?;
+            let mut hash = [0u8; 16];
+            let mut input = [0u8; 4];
+            hash.copy_from_slice(&record[..16]);
+            input.copy_from_slice(&record[16..]);
+            previous
+                .entry(u128::from_le_bytes(hash))
+                .or_insert_with(|| u32::from_le_bytes(input));
+        }
+        eprintln!(
+            "Seen-set {}: {} hashes from {} inputs",
+            path.to_string_lossy(),
+            previous.len(),
+            inputs.len()
+        );
+
+        Ok(SeenSet {
+            path: path.to_path_buf(),
+            state: Mutex::new(SeenState {
+                inputs,
+                inputs_log,
+                previous,
+                current: HashSet::new(),
+                log: BufWriter::new(log),
+            }),
+        })
+    }
+
+    // The id of the input file `name`, registered on first use.
+    pub fn input_id(&self, name: &str) -> io::Result<u32> {
+        let mut state = self.state.lock().expect("seen-set lock");
+        if let Some(id) = state.inputs.get(name) {
+            return Ok(*id);
+        }
+        let id = state.inputs.len() as u32;
+        writeln!(state.inputs_log, "{}", name)?;
+        state.inputs.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    // Returns true if `hash` was seen before, otherwise remembers it as seen
+    // in `input`.
+    pub fn check(&self, hash: u128, input: u32) -> io::Result<bool> {
+        let mut state = self.state.lock().expect("seen-set lock");
+        if state.current.contains(&hash) {
+            return Ok(true);
+        }
+        if let Some(first) = state.previous.get(&hash) {
+            if *first != input {
+                return Ok(true);
+            }
+        }
+        state.current.insert(hash);
+        state.log.write_all(&hash.to_le_bytes())?;
+        state.log.write_all(&input.to_le_bytes())?;
+        Ok(false)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock().expect("seen-set lock");
+        state.log.flush()
+    }
+}
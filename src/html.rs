@@ -0,0 +1,191 @@
+// Elements whose tags end a line of text, like block elements do when
+// rendered. WET files have the text of every block on a line of its own.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+// Elements whose content is not text of the page.
+const SKIPPED: &[&str] = &["script", "style", "noscript", "template", "svg", "title"];
+
+const ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("quot", "\""),
+    ("apos", "'"),
+    ("nbsp", " "),
+    ("ndash", "–"),
+    ("mdash", "—"),
+    ("hellip", "…"),
+    ("lsquo", "‘"),
+    ("rsquo", "’"),
+    ("ldquo", "“"),
+    ("rdquo", "”"),
+    ("laquo", "«"),
+    ("raquo", "»"),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("euro", "€"),
+];
+
+// The title and text of an HTML page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HtmlText {
+    pub title: Option<String>,
+    pub text: String,
+}
+
+// Replaces character references, numeric ones and the most common named
+// ones. Others are kept as they are.
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.bytes().take(12).position(|b| b == b';') {
+            Some(end) => end,
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name = &rest[1..end];
+        let number = match name.strip_prefix('#') {
+            Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+            Some(decimal) => decimal.parse::<u32>().ok(),
+            None => None,
+        };
+        let replacement = match number {
+            Some(number) => std::char::from_u32(number).map(|c| c.to_string()),
+            None => ENTITIES
+                .iter()
+                .find(|(entity, _)| *entity == name)
+                .map(|(_, text)| text.to_string()),
+        };
+        match replacement {
+            Some(replacement) => {
+                result.push_str(&replacement);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Appends `text` with runs of whitespace collapsed to a space, as browsers
+// show it.
+fn push_text(lines: &mut String, text: &str) {
+    let mut space = false;
+    for c in unescape(text).chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && !lines.is_empty() && !lines.ends_with([' ', '\n']) {
+            lines.push(' ');
+        }
+        lines.push(c);
+        space = false;
+    }
+    if space && !lines.is_empty() && !lines.ends_with([' ', '\n']) {
+        lines.push(' ');
+    }
+}
+
+// The text of `html` with a line per block, without markup, scripts and
+// styles, and the text of its `<title>`. Meant for indexing, not for
+// rendering: there is no tree, broken markup just yields more or less text.
+pub fn extract(html: &str) -> HtmlText {
+    // same byte offsets as `html`, for finding tags regardless of case
+    let lower = html.to_ascii_lowercase();
+    let mut title = None;
+    let mut lines = String::new();
+    let mut position = 0;
+    while let Some(start) = lower[position..].find('<').map(|i| position + i) {
+        push_text(&mut lines, &html[position..start]);
+        if lower[start..].starts_with("<!--") {
+            position = lower[start..]
+                .find("-->")
+                .map_or(html.len(), |end| start + end + 3);
+            continue;
+        }
+        let end = match lower[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                position = html.len();
+                break;
+            }
+        };
+        position = end + 1;
+        let tag = lower[start + 1..end].trim_start_matches('/');
+        let name_end = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        let closing = lower[start + 1..].starts_with('/');
+        if !closing && SKIPPED.contains(&name) {
+            let close = format!("</{}", name);
+            let content_end = lower[position..]
+                .find(&close)
+                .map_or(html.len(), |i| position + i);
+            if name == "title" && title.is_none() {
+                let mut text = String::new();
+                push_text(&mut text, &html[position..content_end]);
+                title = Some(text.trim().to_string()).filter(|title| !title.is_empty());
+            }
+            position = lower[content_end..]
+                .find('>')
+                .map_or(html.len(), |i| content_end + i + 1);
+            continue;
+        }
+        if BLOCKS.contains(&name) && !lines.ends_with('\n') {
+            lines.push('\n');
+        }
+    }
+    push_text(&mut lines, &html[position..]);
+    let text = lines
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    HtmlText { title, text }
+}
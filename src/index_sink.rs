@@ -0,0 +1,155 @@
+use std;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use serde::Serialize;
+use tantivy::merge_policy::MergePolicy;
+use tantivy::schema::Term;
+use tantivy::Index;
+use tantivy::IndexWriter;
+
+use crate::ndjson::NdjsonEncoder;
+use crate::schema;
+use crate::schema::{BodyStore, DocFields, SchemaOptions, SourceType};
+use crate::sink::DocSink;
+use crate::tokenizers;
+use crate::warc::DocJson;
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+// The tantivy index in <index> shared by all tasks. Documents are added under
+// the read lock, every task commits its input file under the write lock.
+pub struct IndexTarget {
+    pub index: Index,
+    writer: RwLock<IndexWriter>,
+    fields: DocFields,
+    // delete documents with the same `uri` or `record_id` before adding
+    upsert: bool,
+}
+
+impl fmt::Debug for IndexTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("IndexTarget")
+    }
+}
+
+impl IndexTarget {
+    // Opens the index in `dir` or creates it with the schema for `source`.
+    pub fn open(
+        dir: &Path,
+        source: SourceType,
+        options: SchemaOptions,
+        threads: usize,
+        heap_size: usize,
+        upsert: bool,
+    ) -> io::Result<IndexTarget> {
+        let expected = schema::build_schema(source, options);
+        let index = schema::open_or_create_index(dir, &expected)?;
+        tokenizers::register(&index);
+        let mut fields = DocFields::resolve(&index.schema())?;
+        if let BodyStore::Truncate(chars) = options.body_store {
+            fields.excerpt_chars = chars;
+        }
+        let writer = index
+            .writer_with_num_threads(threads, heap_size)
+            .map_err(to_io_error)?;
+        Ok(IndexTarget {
+            index,
+            writer: RwLock::new(writer),
+            fields,
+            upsert,
+        })
+    }
+
+    // Merges segments as `policy` picks them after every commit.
+    pub fn set_merge_policy(&self, policy: Box<dyn MergePolicy>) {
+        let writer = self.writer.read().expect("index writer lock");
+        writer.set_merge_policy(policy);
+    }
+}
+
+// A document's embedding with its id, the `record_id` or else the `uri`.
+#[derive(Serialize)]
+struct IdEmbedding<'a> {
+    id: &'a str,
+    embedding: &'a [f32],
+}
+
+pub struct IndexSink {
+    target: Arc<IndexTarget>,
+    // the index has no vector fields, embeddings go to this NDJSON file,
+    // created with the first one
+    embeddings_path: PathBuf,
+    embeddings: Option<NdjsonEncoder<BufWriter<File>>>,
+}
+
+impl IndexSink {
+    pub fn new(target: Arc<IndexTarget>, embeddings_path: PathBuf) -> IndexSink {
+        IndexSink {
+            target,
+            embeddings_path,
+            embeddings: None,
+        }
+    }
+
+    fn write_embedding(&mut self, doc: &DocJson, embedding: &[f32]) -> io::Result<()> {
+        if self.embeddings.is_none() {
+            if let Some(dir) = self.embeddings_path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let file = File::create(&self.embeddings_path)?;
+            self.embeddings = Some(NdjsonEncoder::new(BufWriter::new(file)));
+        }
+        let id = doc.record_id.as_deref().unwrap_or(&doc.uri);
+        let encoder = self.embeddings.as_mut().expect("embeddings file");
+        encoder.encode(&IdEmbedding { id, embedding })?;
+        Ok(())
+    }
+}
+
+impl DocSink for IndexSink {
+    fn write_batch(&mut self, batch: &[DocJson]) -> io::Result<()> {
+        for doc in batch {
+            if let Some(embedding) = &doc.embedding {
+                self.write_embedding(doc, embedding)?;
+            }
+        }
+        let writer = self.target.writer.read().expect("index writer lock");
+        let fields = &self.target.fields;
+        for doc in batch {
+            // passages after the first share the page's `uri` and `parent_id`,
+            // the first already deleted the old copies
+            if self.target.upsert && doc.passage.unwrap_or(0) == 0 {
+                // deletes only affect documents added before them, so a
+                // re-crawled page replaces the old copy but not itself
+                if !doc.uri.is_empty() {
+                    writer.delete_term(Term::from_field_text(fields.uri, &doc.uri));
+                }
+                if let (Some(field), Some(record_id)) = (fields.record_id, &doc.record_id) {
+                    writer.delete_term(Term::from_field_text(field, record_id));
+                }
+                if let (Some(field), Some(parent_id)) = (fields.parent_id, &doc.parent_id) {
+                    writer.delete_term(Term::from_field_text(field, parent_id));
+                }
+            }
+            writer.add_document(fields.document(doc));
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        if let Some(embeddings) = self.embeddings {
+            embeddings.into_inner().flush()?;
+        }
+        let mut writer = self.target.writer.write().expect("index writer lock");
+        writer.commit().map_err(to_io_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use std;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+// uncompressed inputs are only picked up with one of these extensions
+const PLAIN_EXTENSIONS: &[&str] = &["wet", "warc", "xml"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub fn from_magic(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_extension(extension: &OsStr) -> Option<Compression> {
+        match extension.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "bz2" => Some(Compression::Bzip2),
+            "zst" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            ext if PLAIN_EXTENSIONS.contains(&ext) => Some(Compression::None),
+            _ => None,
+        }
+    }
+
+    // The magic bytes win over the extension, so a mislabeled file is still
+    // decoded correctly. Files without known magic fall back to the extension.
+    pub fn detect(path: &Path) -> io::Result<Compression> {
+        let mut magic = [0u8; 6];
+        let mut file = File::open(path)?;
+        let mut read = 0;
+        while read < magic.len() {
+            match file.read(&mut magic[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        if let Some(compression) = Compression::from_magic(&magic[..read]) {
+            return Ok(compression);
+        }
+        match path.extension().and_then(Compression::from_extension) {
+            Some(Compression::None) => Ok(Compression::None),
+            // extension claims compression, but the content is not
+            Some(compression) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "extension says {} but magic bytes do not match",
+                    compression
+                ),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "neither a known compression format nor one of wet, warc, xml",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "uncompressed",
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        };
+        f.write_str(name)
+    }
+}
+
+// Opens an input file of any source type, picking the decoder by magic bytes
+// and extension. All decoders handle multi-stream/multi-frame files.
+pub fn open(path: &Path, capacity: usize) -> io::Result<Box<dyn BufRead + Send>> {
+    let compression = Compression::detect(path)?;
+    let file = File::open(path)?;
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(file)),
+    };
+    Ok(Box::new(io::BufReader::with_capacity(capacity, reader)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEXT: &[u8] = b"WARC/1.0\r\nWARC-Type: warcinfo\r\n\r\n";
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::stream::encode_all(data, 0).unwrap(),
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    // Writes `data` to `name` in `dir` and reads it back with `open`.
+    fn round_trip(dir: &Path, name: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        let mut text = Vec::new();
+        open(&path, 64)?.read_to_end(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let codecs = [
+            (Compression::Gzip, "gz"),
+            (Compression::Bzip2, "bz2"),
+            (Compression::Zstd, "zst"),
+            (Compression::Xz, "xz"),
+        ];
+        for (compression, extension) in codecs.iter() {
+            let data = compress(*compression, TEXT);
+            assert_eq!(Compression::from_magic(&data), Some(*compression));
+            assert_eq!(
+                Compression::from_extension(OsStr::new(extension)),
+                Some(*compression)
+            );
+            let name = format!("input.warc.wet.{}", extension);
+            assert_eq!(round_trip(dir.path(), &name, &data).unwrap(), TEXT);
+            // every codec reads files of several streams to the end
+            let streams = [data.clone(), compress(*compression, TEXT)].concat();
+            let name = format!("streams.warc.{}", extension);
+            let text = round_trip(dir.path(), &name, &streams).unwrap();
+            assert_eq!(text, [TEXT, TEXT].concat());
+        }
+        assert_eq!(round_trip(dir.path(), "input.wet", TEXT).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn magic_bytes_win_over_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let misnamed = [
+            (Compression::Bzip2, "input.warc.gz"),
+            (Compression::Zstd, "input.warc.xz"),
+            (Compression::Gzip, "input.warc"),
+            (Compression::Xz, "input.xml"),
+        ];
+        for (compression, name) in misnamed.iter() {
+            let data = compress(*compression, TEXT);
+            std::fs::write(dir.path().join(name), &data).unwrap();
+            assert_eq!(
+                Compression::detect(&dir.path().join(name)).unwrap(),
+                *compression
+            );
+            assert_eq!(round_trip(dir.path(), name, &data).unwrap(), TEXT);
+        }
+        // compressed by extension only, or neither compressed nor a known input
+        let error = round_trip(dir.path(), "plain.warc.zst", TEXT).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = round_trip(dir.path(), "notes.txt", TEXT).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,150 @@
+use std;
+
+use whatlang::Lang;
+
+// whatlang is accurate enough on the first few kilobytes of a page
+const DETECT_PREFIX_BYTES: usize = 4096;
+
+// Macrolanguage codes, as in Common Crawl's headers, and the code of the
+// individual language whatlang, the tokenizers and `--lang` use for them.
+const MACROLANGUAGES: &[(&str, &str)] = &[("zho", "cmn"), ("nor", "nob"), ("fas", "pes")];
+
+// The lower cased code, or the individual language of a macrolanguage.
+pub fn normalize(code: &str) -> String {
+    let code = code.trim().to_lowercase();
+    MACROLANGUAGES
+        .iter()
+        .find(|(macrolanguage, _)| *macrolanguage == code)
+        .map_or(code, |(_, language)| language.to_string())
+}
+
+// Common Crawl's `WARC-Identified-Content-Language` holds one or more ISO 639-3
+// codes, most likely first, e.g. `eng,deu`.
+pub fn from_header(value: &str) -> Option<String> {
+    value
+        .split(',')
+        .map(|code| code.trim())
+        .find(|code| !code.is_empty())
+        .map(normalize)
+}
+
+// Offline trigram based detection, returns an ISO 639-3 code like the WARC
+// header or `None` when whatlang is not confident.
+pub fn detect(text: &str) -> Option<String> {
+    let mut end = std::cmp::min(text.len(), DETECT_PREFIX_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    whatlang::detect(&text[..end])
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().code().to_string())
+}
+
+pub fn is_known_code(code: &str) -> bool {
+    Lang::from_code(&normalize(code)).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Pipeline;
+    use crate::schema::{self, DocFields, SchemaOptions, SourceType};
+    use crate::warc::DocJson;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog while the farmer \
+                           watches from the porch and drinks his morning coffee.";
+
+    #[test]
+    fn header_codes() {
+        assert_eq!(from_header("eng").as_deref(), Some("eng"));
+        assert_eq!(from_header("deu,eng,fra").as_deref(), Some("deu"));
+        assert_eq!(from_header(" , spa ").as_deref(), Some("spa"));
+        assert_eq!(from_header(""), None);
+        // macrolanguages as their individual language, like whatlang detects them
+        assert_eq!(from_header("zho,eng").as_deref(), Some("cmn"));
+        assert_eq!(from_header("NOR").as_deref(), Some("nob"));
+        assert!(is_known_code("zho"));
+    }
+
+    #[test]
+    fn macrolanguage_header_kept_by_lang_filter() {
+        let mut pipeline = Pipeline::default();
+        let languages = ["zho"].iter().map(|code| normalize(code)).collect();
+        pipeline.languages = Some(std::sync::Arc::new(languages));
+        let mut doc = DocJson {
+            body: "ok 42".to_string(),
+            language: from_header("zho"),
+            ..DocJson::default()
+        };
+        assert!(pipeline.process(&mut doc).unwrap());
+        assert_eq!(doc.language.as_deref(), Some("cmn"));
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(detect(ENGLISH).as_deref(), Some("eng"));
+        let german = "Der schnelle braune Fuchs springt über den faulen Hund, während \
+                      der Bauer von der Veranda aus zusieht und seinen Kaffee trinkt.";
+        assert_eq!(detect(german).as_deref(), Some("deu"));
+        // too short or too mixed to be reliable
+        assert_eq!(detect("ok"), None);
+        assert_eq!(detect(""), None);
+        // the prefix ends inside a multi-byte char, which must not panic
+        let long = format!("a{}", "ü".repeat(DETECT_PREFIX_BYTES));
+        let _ = detect(&long);
+        assert!(is_known_code("deu"));
+        assert!(!is_known_code("xx"));
+    }
+
+    #[test]
+    fn header_wins_over_detection() {
+        let pipeline = Pipeline::default();
+        let mut doc = DocJson {
+            uri: "http://example.com/".to_string(),
+            body: ENGLISH.to_string(),
+            language: Some("fra".to_string()),
+            ..DocJson::default()
+        };
+        assert!(pipeline.process(&mut doc).unwrap());
+        assert_eq!(doc.language.as_deref(), Some("fra"));
+
+        let mut doc = DocJson {
+            body: ENGLISH.to_string(),
+            ..DocJson::default()
+        };
+        assert!(pipeline.process(&mut doc).unwrap());
+        assert_eq!(doc.language.as_deref(), Some("eng"));
+    }
+
+    #[test]
+    fn low_confidence_falls_back() {
+        let mut pipeline = Pipeline::default();
+        let mut doc = DocJson {
+            body: "ok 42".to_string(),
+            ..DocJson::default()
+        };
+        assert!(pipeline.process(&mut doc).unwrap());
+        assert_eq!(doc.language, None);
+
+        // without a language, the generic fields of a multilingual index
+        let options = SchemaOptions {
+            multilingual: true,
+            ..SchemaOptions::default()
+        };
+        let fields = DocFields::resolve(&schema::build_schema(SourceType::Warc, options)).unwrap();
+        assert_eq!(fields.text_fields(None), (fields.title, fields.body));
+        assert_ne!(fields.text_fields(Some("deu")), (fields.title, fields.body));
+
+        // and dropped when only some languages are kept
+        pipeline.languages = Some(std::sync::Arc::new(
+            ["eng".to_string()].iter().cloned().collect(),
+        ));
+        let mut doc = DocJson {
+            body: "ok 42".to_string(),
+            ..DocJson::default()
+        };
+        assert!(!pipeline.process(&mut doc).unwrap());
+        let filtered = pipeline.stats.filtered.lock().unwrap();
+        assert_eq!(filtered.get("lang"), Some(&1));
+    }
+}
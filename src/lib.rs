@@ -1,4 +1,30 @@
 //#![feature(io_read_to_string)]
-pub mod warc;
+pub mod chunk;
+pub mod columnar;
+pub mod combine;
+pub mod config;
+pub mod dates;
+pub mod decode;
+pub mod embed;
+pub mod exact_dup;
+pub mod html;
+pub mod index_sink;
+pub mod input;
+pub mod lang;
+pub mod merge;
+pub mod ndjson;
+pub mod near_dup;
+pub mod pipeline;
 pub mod pubmed;
+pub mod quality;
+pub mod schema;
+pub mod search;
+pub mod segment;
+pub mod server;
+pub mod sink;
+pub mod stats;
+pub mod tokenizers;
+pub mod url_filter;
+pub mod urls;
+pub mod warc;
 pub mod wikipedia_abstract;
@@ -1,21 +1,44 @@
 //#![feature(associated_type_bounds)]
 use std;
-use std::ffi::OsStr;
-use std::fs::File;
-use std::io;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 
+use config::JobConfig;
 use docopt::Docopt;
+use merge::MergePolicyOption;
 extern crate tantivy;
-use flate2::read::MultiGzDecoder;
-use tantivy::Index;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+use schema::SourceType;
 use tokio::sync::Semaphore;
 // use warc::send_to_quickwit;
-use warc::DocJson;
 
+mod chunk;
+mod columnar;
+mod combine;
+mod config;
+mod dates;
+mod decode;
+mod embed;
+mod exact_dup;
+mod html;
+mod index_sink;
+mod input;
+mod lang;
+mod merge;
+mod ndjson;
+mod near_dup;
+mod pipeline;
 mod pubmed;
+mod quality;
+mod schema;
+mod search;
+mod segment;
+mod server;
+mod sink;
+mod stats;
+mod tokenizers;
+mod url_filter;
+mod urls;
 mod warc;
 mod wikipedia_abstract;
 
@@ -23,58 +46,166 @@ const USAGE: &'static str = "
 WARC Indexer
 
 Usage:
-  warc_parser [-t <threads>] [--from <from>] [--to <to>] -s <format> <index> <warc_dir>
+  warc_parser [options] -s <format> <index> <warc_dir>
+  warc_parser [options] --config <file> [<index> <warc_dir>]
+  warc_parser search [--limit <hits>] [--json] <index> <query>
+  warc_parser serve [--listen <addr>] <index>
+  warc_parser stats [--json] [--top <n>] [--terms <fields>] <index>
+  warc_parser merge [--segments <n>] [--merge-policy <policy>] <index>
+  warc_parser compact <index>
+  warc_parser gc <index>
+  warc_parser combine [-t <threads>] <index> <shards>...
   warc_parser (-h | --help)
 
 Options:
-  -h --help      Show this help
-  -s <source>    type of source files (WARC or ENTREZ or WIKIPEDIA_ABSTRACT)
-  -t <threads>   number of threads to use, default 4
-  --from <from>  skip files until from
-  --to <to>      skip files after to
+  -h --help                Show this help
+  --config <file>          read the job from this TOML file, options given here override it
+  -s <source>              type of source files (WARC or ENTREZ or WIKIPEDIA_ABSTRACT)
+  -t <threads>             number of threads to use, default 4
+  --from <from>            skip files until from
+  --to <to>                skip files after to
+  --shard <shard>          only process the inputs of shard <i>/<n>, counted from 0, e.g. 2/8
+  --tasks <tasks>          number of files processed at the same time, default 16
+  --batch-size <docs>      documents written at once, default 10 for WARC and 1000 otherwise
+  --format <format>        output format (ndjson, parquet or index), default ndjson
+  --compress <codec>       compress the output (none, gzip or zstd), default none
+  --max-docs <docs>        start a new ndjson part after this many documents
+  --max-bytes <bytes>      start a new ndjson part after this many uncompressed bytes
+  --row-group-size <rows>  rows per parquet row group, default 65536
+  --lang <codes>           only keep documents in these comma separated ISO 639-3 languages, e.g. eng,deu
+  --multilingual           add per-language title and body fields to a new index
+  --sentence-positions     keep phrase queries on the body fields of a new index within sentences
+  --store-body <mode>      keep bodies of a new index as full, zstd, truncate=<chars> or none, default full; none makes snippets decompress input files; combine needs full or zstd
+  --chunk <passages>       index passages of words[=<n>], sentences[=<n>] or paragraphs[=<n>], with overlap=<n>, instead of whole bodies
+  --upsert                 replace indexed documents with the same uri or record id
+  --merge-policy <policy>  merge segments while indexing and for merge: log[,min-segments=<n>][,max-docs=<n>] or none, default log
+  --url-rules <file>       only process records whose URI passes the +include and -exclude patterns in this file
+  --block-hosts <file>     skip records of the hosts in this file, one per line, and their subdomains
+  --quality <rules>        drop documents failing these comma separated rules, e.g. min-words=50,min-alpha-ratio=0.7
+  --bad-words <file>       drop documents containing one of the words in this file, one per line
+  --dedup <file>           drop documents with a body seen before, in this run or runs sharing the seen-set file
+  --near-dup <mode>        drop or tag documents whose SimHash is close to an earlier one
+  --near-dup-bits <bits>   max differing bits of near duplicate SimHashes, default 3
+  --embed <embedder>       add an embedding to every document, from hash or an embedding server URL
+  --embeddings-dir <dir>   directory of the embeddings of --format index, default <index>.embeddings
+  --limit <hits>           number of search hits to print, default 10
+  --json                   print search hits or stats as JSON
+  --listen <addr>          address to serve the index on, default 127.0.0.1:8080
+  --top <n>                number of domains, TLDs and languages listed by stats, default 10
+  --terms <fields>         comma separated fields to count terms of, default all indexed text fields
+  --segments <n>           merge the index down to at most this many segments
 ";
 
-#[derive(Debug)]
-enum SourceType {
-    WARC,
-    WIKIPEDIA_ABSTRACT,
-    ENTREZ,
-}
-
-#[derive(Debug)]
-struct Args {
-    arg_index: Vec<String>,
-    arg_warc_dir: Vec<String>,
-    flag_threads: usize,
-    flag_source: SourceType,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
     let args = Docopt::new(USAGE)
         .and_then(|d| d.argv(std::env::args().into_iter()).parse())
         .unwrap_or_else(|e| e.exit());
 
-    let source_type = args.get_str("-s").to_string();
-    let index_dir = args.get_str("<index>");
-    let warc_dir = args.get_str("<warc_dir>");
-    let threads = args.get_str("-t");
-    let from = args.get_str("--from").parse::<usize>().unwrap_or(0);
-    let to = args.get_str("--to").parse::<usize>().unwrap_or(usize::MAX);
-    let nthreads: usize = threads.parse().unwrap_or(4);
-    const PER_THREAD_BUF_SIZE: usize = 600 * 1024 * 1024;
+    if args.get_bool("search") {
+        let limit = args.get_str("--limit").parse::<usize>().unwrap_or(10);
+        return search::run(
+            Path::new(args.get_str("<index>")),
+            args.get_str("<query>"),
+            limit,
+            args.get_bool("--json"),
+        );
+    }
+    if args.get_bool("serve") {
+        let listen = match args.get_str("--listen") {
+            "" => "127.0.0.1:8080",
+            listen => listen,
+        };
+        return server::run(Path::new(args.get_str("<index>")), listen).await;
+    }
+    if args.get_bool("stats") {
+        let options = stats::StatsOptions {
+            top: args.get_str("--top").parse::<usize>().unwrap_or(10),
+            term_fields: args
+                .get_str("--terms")
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .collect(),
+        };
+        return stats::run(
+            Path::new(args.get_str("<index>")),
+            &options,
+            args.get_bool("--json"),
+        );
+    }
+    if args.get_bool("merge") {
+        let policy = MergePolicyOption::parse(args.get_str("--merge-policy"))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let segments = args.get_str("--segments").parse::<usize>().ok();
+        return merge::merge(Path::new(args.get_str("<index>")), segments, &policy).await;
+    }
+    if args.get_bool("compact") {
+        return merge::compact(Path::new(args.get_str("<index>"))).await;
+    }
+    if args.get_bool("gc") {
+        return merge::gc(Path::new(args.get_str("<index>"))).await;
+    }
+    if args.get_bool("combine") {
+        let limits = config::LimitsConfig::default();
+        let threads = args
+            .get_str("-t")
+            .parse::<usize>()
+            .unwrap_or(limits.threads);
+        let shards: Vec<&Path> = args
+            .get_vec("<shards>")
+            .into_iter()
+            .map(Path::new)
+            .collect();
+        return combine::run(
+            Path::new(args.get_str("<index>")),
+            &shards,
+            threads,
+            limits.writer_heap,
+        );
+    }
+
+    let mut config = if args.get_str("--config").is_empty() {
+        JobConfig::default()
+    } else {
+        JobConfig::load(Path::new(args.get_str("--config")))?
+    };
+    let mut errors = config.apply_args(&args);
+    errors.extend(config.validate());
+    if !errors.is_empty() {
+        for error in errors {
+            eprintln!("Invalid job: {}", error);
+        }
+        std::process::exit(1)
+    }
+
+    let source_type = config.source_type();
+    let index_dir = config.index.as_str();
+    let warc_dir = config.inputs.as_str();
+    let from = config.from.unwrap_or(0);
+    let to = config.to.unwrap_or(usize::MAX);
+    let shard = config.shard();
+    let nthreads = config.limits.threads;
+    let read_buffer = config.limits.read_buffer;
+    let output = config.output_options()?;
+    let pipeline = config.pipeline()?;
 
     println!("Only indexing files: {} - {}", from, to);
+    if let Some(shard) = shard {
+        println!("Shard: {}/{}", shard.index, shard.count);
+    }
     println!("Out dir: {:?}", index_dir);
     println!("Warc dir: {:?}", warc_dir);
     println!("Threads: {:?}", nthreads);
+    eprintln!("Output: {}", output);
+    eprintln!("Pipeline: {}", pipeline);
     println!("");
 
     // let (tx, rx) = tokio::sync::mpsc::channel::<DocJson>(1000);
 
     let mut numfiles = 0;
     let mut tasks = Vec::new();
-    let semaphore = std::sync::Arc::new(Semaphore::new(16));
+    let semaphore = std::sync::Arc::new(Semaphore::new(config.limits.tasks));
 
     // let sender = tokio::spawn(async move {
     //     send_to_quickwit(rx).await;
@@ -87,86 +218,76 @@ async fn main() -> Result<(), std::io::Error> {
             continue;
         }
         let filename = path.path().clone();
+        if let Some(shard) = shard {
+            if !shard.contains(&filename.file_name().unwrap().to_string_lossy()) {
+                continue;
+            }
+        }
         let mut out_file_path = PathBuf::from(index_dir).join(filename.file_stem().unwrap());
         out_file_path.set_extension("json");
 
-        let source_type_clone = source_type.clone();
+        let output = output.clone();
+        let mut pipeline =
+            match pipeline.for_input(&filename.file_name().unwrap().to_string_lossy()) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    eprintln!("Skip file {}: {}", filename.to_string_lossy(), e);
+                    pipeline.stats.failed_files.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+        let source_file = std::fs::canonicalize(&filename).unwrap_or_else(|_| filename.clone());
+        pipeline.source_file = Some(source_file.to_string_lossy().to_string());
         let permit = semaphore.clone().acquire_owned().await.unwrap();
         // let tx_clone = tx.clone();
         tasks.push(tokio::task::spawn(async move {
-            let file = File::open(&filename).unwrap();
-
             eprintln!("{}\t{}", numfiles, filename.to_string_lossy());
-            match filename.extension() {
-                Some(extension) => {
-                    if extension == OsStr::new("gz") {
-                        println!("gzipped {}", source_type_clone);
-                        match dbg!(source_type_clone.as_str()) {
-                            "WARC" => {
-                                if let Err(e) = warc::extract_records_and_push_to_quickwit(
-                                    &mut io::BufReader::with_capacity(
-                                        PER_THREAD_BUF_SIZE,
-                                        MultiGzDecoder::new(file),
-                                    ),
-                                    out_file_path,
-                                )
-                                .await
-                                {
-                                    eprintln!(
-                                        "Error processing file {}: {:?}",
-                                        filename.to_string_lossy(),
-                                        e
-                                    );
-                                }
-                            }
-                            "WIKIPEDIA_ABSTRACT" => {
-                                if let Err(e) = wikipedia_abstract::extract_records_and_add_to_json(
-                                    &mut io::BufReader::with_capacity(
-                                        PER_THREAD_BUF_SIZE,
-                                        MultiGzDecoder::new(file),
-                                    ),
-                                    out_file_path,
-                                )
-                                .await
-                                {
-                                    eprintln!(
-                                        "Error processing file {}: {:?}",
-                                        filename.to_string_lossy(),
-                                        e
-                                    );
-                                }
-                            }
-                            "ENTREZ" => {
-                                if let Err(e) = pubmed::extract_records_and_add_to_json(
-                                    &mut io::BufReader::with_capacity(
-                                        PER_THREAD_BUF_SIZE,
-                                        MultiGzDecoder::new(file),
-                                    ),
-                                    out_file_path,
-                                )
-                                .await
-                                {
-                                    eprintln!(
-                                        "Error processing file {}: {:?}",
-                                        filename.to_string_lossy(),
-                                        e
-                                    );
-                                }
-                            }
-                            _ => eprintln!("Unknown source type {}", source_type_clone),
-                        }
-                    } else if extension == OsStr::new("wet") {
-                        warc::extract_records_and_push_to_quickwit(
-                            &mut io::BufReader::with_capacity(PER_THREAD_BUF_SIZE, file),
-                            out_file_path,
-                        )
-                        .await
-                        .unwrap();
-                    } else {
-                        eprintln!("Skip file, neither wet nor gz");
-                    }
+            let reader = match input::open(&filename, read_buffer) {
+                Ok(reader) => reader,
+                Err(e) => {
+                    eprintln!("Skip file {}: {}", filename.to_string_lossy(), e);
+                    pipeline.stats.failed_files.fetch_add(1, Ordering::Relaxed);
+                    drop(permit);
+                    return;
+                }
+            };
+
+            let result = match source_type {
+                SourceType::Warc => {
+                    warc::extract_records_and_push_to_quickwit(
+                        reader,
+                        out_file_path,
+                        &output,
+                        &pipeline,
+                    )
+                    .await
+                }
+                SourceType::WikipediaAbstract => {
+                    wikipedia_abstract::extract_records_and_add_to_json(
+                        reader,
+                        out_file_path,
+                        &output,
+                        &pipeline,
+                    )
+                    .await
+                }
+                SourceType::Entrez => {
+                    pubmed::extract_records_and_add_to_json(
+                        reader,
+                        out_file_path,
+                        &output,
+                        &pipeline,
+                    )
+                    .await
                 }
-                None => eprintln!("Skip file, neither wet nor gz"),
+            };
+            if let Err(e) = result.and_then(|_| pipeline.flush()) {
+                eprintln!(
+                    "Error processing file {}: {:?}",
+                    filename.to_string_lossy(),
+                    e
+                );
+                pipeline.stats.failed_files.fetch_add(1, Ordering::Relaxed);
             }
             drop(permit);
         }))
@@ -177,6 +298,8 @@ async fn main() -> Result<(), std::io::Error> {
             eprintln!("Task error: {:?}", e);
         }
     }
+    pipeline.flush()?;
+    println!("{}", pipeline.stats);
     // let _ = tokio::join!(sender);
     Ok(())
 }
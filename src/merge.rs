@@ -0,0 +1,204 @@
+use std;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use tantivy::merge_policy::{LogMergePolicy, MergePolicy, NoMergePolicy};
+use tantivy::{Index, IndexWriter, SegmentId};
+
+use crate::tokenizers;
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// Memory of the writer used for merging, which adds no documents.
+const WRITER_HEAP: usize = 50 * 1024 * 1024;
+
+// The merge policy while indexing and for `merge`, `--merge-policy`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergePolicyOption {
+    // tantivy's `LogMergePolicy`, with its defaults where not given
+    Log {
+        min_segments: Option<usize>,
+        max_docs: Option<usize>,
+    },
+    // never merge, e.g. to merge once with `compact` after indexing
+    None,
+}
+
+impl Default for MergePolicyOption {
+    fn default() -> Self {
+        MergePolicyOption::Log {
+            min_segments: None,
+            max_docs: None,
+        }
+    }
+}
+
+impl MergePolicyOption {
+    // Parses `none` or `log` followed by comma separated `name=value`
+    // settings, e.g. `log,min-segments=4,max-docs=5000000`.
+    pub fn parse(policy: &str) -> Result<MergePolicyOption, String> {
+        let mut parts = policy.split(',').map(|part| part.trim());
+        match parts.next() {
+            Some("none") if parts.next().is_none() => Ok(MergePolicyOption::None),
+            Some("") | Some("log") => {
+                let (mut min_segments, mut max_docs) = (None, None);
+                for setting in parts {
+                    let (name, value) = setting
+                        .split_once('=')
+                        .ok_or_else(|| format!("merge policy setting {} has no value", setting))?;
+                    let value = value
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|value| *value > 0)
+                        .ok_or_else(|| format!("merge policy setting {} needs a count", name))?;
+                    match name.trim() {
+                        "min-segments" => min_segments = Some(value),
+                        "max-docs" => max_docs = Some(value),
+                        _ => return Err(format!("unknown merge policy setting {}", name)),
+                    }
+                }
+                Ok(MergePolicyOption::Log {
+                    min_segments,
+                    max_docs,
+                })
+            }
+            _ => Err(format!("unknown merge policy {}", policy)),
+        }
+    }
+
+    pub fn policy(&self) -> Box<dyn MergePolicy> {
+        match self {
+            MergePolicyOption::Log {
+                min_segments,
+                max_docs,
+            } => {
+                let mut policy = LogMergePolicy::default();
+                if let Some(min_segments) = min_segments {
+                    policy.set_min_num_segments(*min_segments);
+                }
+                if let Some(max_docs) = max_docs {
+                    policy.set_max_docs_before_merge(*max_docs);
+                }
+                Box::new(policy)
+            }
+            MergePolicyOption::None => Box::new(NoMergePolicy),
+        }
+    }
+}
+
+// Splits `segments`, given with their number of documents, into at most `n`
+// groups holding about the same number of documents.
+pub fn merge_groups<T>(mut segments: Vec<(T, u32)>, n: usize) -> Vec<Vec<T>> {
+    segments.sort_by_key(|(_, docs)| Reverse(*docs));
+    let mut groups: Vec<(u64, Vec<T>)> = Vec::new();
+    for (segment, docs) in segments {
+        if groups.len() < n.max(1) {
+            groups.push((docs as u64, vec![segment]));
+            continue;
+        }
+        let smallest = groups
+            .iter_mut()
+            .min_by_key(|(group_docs, _)| *group_docs)
+            .expect("at least one group");
+        smallest.0 += docs as u64;
+        smallest.1.push(segment);
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+// Opens the index in `dir` with a writer that merges only when told to. The
+// writer holds the index lock, so this fails while an indexer is running.
+fn open_writer(dir: &Path) -> io::Result<(Index, IndexWriter)> {
+    let index = Index::open_in_dir(dir).map_err(to_io_error)?;
+    tokenizers::register(&index);
+    let writer = index.writer(WRITER_HEAP).map_err(to_io_error)?;
+    writer.set_merge_policy(Box::new(NoMergePolicy));
+    Ok((index, writer))
+}
+
+fn segment_count(index: &Index) -> io::Result<usize> {
+    Ok(index.searchable_segment_ids().map_err(to_io_error)?.len())
+}
+
+// Merges the index down to at most `segments` segments. A segment left on
+// its own is only rewritten if that drops deleted documents.
+async fn force_merge(index: &Index, writer: &mut IndexWriter, segments: usize) -> io::Result<()> {
+    let metas = index.searchable_segment_metas().map_err(to_io_error)?;
+    let with_deletes: HashSet<SegmentId> = metas
+        .iter()
+        .filter(|meta| meta.has_deletes())
+        .map(|meta| meta.id())
+        .collect();
+    let sizes = metas
+        .iter()
+        .map(|meta| (meta.id(), meta.num_docs()))
+        .collect();
+    for group in merge_groups(sizes, segments) {
+        if group.len() > 1 || with_deletes.contains(&group[0]) {
+            writer.merge(&group).await.map_err(to_io_error)?;
+        }
+    }
+    Ok(())
+}
+
+// Merges what `policy` picks until it picks nothing.
+async fn policy_merge(
+    index: &Index,
+    writer: &mut IndexWriter,
+    policy: &dyn MergePolicy,
+) -> io::Result<()> {
+    loop {
+        let metas = index.searchable_segment_metas().map_err(to_io_error)?;
+        let candidates = policy.compute_merge_candidates(&metas);
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        for candidate in candidates {
+            writer.merge(&candidate.0).await.map_err(to_io_error)?;
+        }
+    }
+}
+
+async fn finish(writer: IndexWriter) -> io::Result<()> {
+    let collected = writer.garbage_collect_files().await.map_err(to_io_error)?;
+    println!("Removed files: {}", collected.deleted_files.len());
+    for file in &collected.failed_to_delete_files {
+        eprintln!("Could not remove {}", file.to_string_lossy());
+    }
+    writer.wait_merging_threads().map_err(to_io_error)
+}
+
+// `merge` subcommand: merges the index in `dir` down to `segments` segments
+// or, without, as `policy` would while indexing.
+pub async fn merge(
+    dir: &Path,
+    segments: Option<usize>,
+    policy: &MergePolicyOption,
+) -> io::Result<()> {
+    let (index, mut writer) = open_writer(dir)?;
+    println!("Segments before: {}", segment_count(&index)?);
+    match segments {
+        Some(segments) => force_merge(&index, &mut writer, segments).await?,
+        None => policy_merge(&index, &mut writer, &*policy.policy()).await?,
+    }
+    println!("Segments after: {}", segment_count(&index)?);
+    finish(writer).await
+}
+
+// `compact` subcommand: merges the index in `dir` into one segment, dropping
+// deleted documents, and removes the files no longer used.
+pub async fn compact(dir: &Path) -> io::Result<()> {
+    merge(dir, Some(1), &MergePolicyOption::None).await
+}
+
+// `gc` subcommand: removes the files of the index in `dir` that no segment
+// uses anymore, e.g. left behind by an interrupted indexer or merge.
+pub async fn gc(dir: &Path) -> io::Result<()> {
+    let (_, writer) = open_writer(dir)?;
+    finish(writer).await
+}
@@ -0,0 +1,31 @@
+use std;
+use std::io;
+use std::io::Write;
+
+use serde::Serialize;
+
+// Newline delimited json: every document is serialized on its own line and
+// every line, including the last one, is terminated by `\n`. serde_json escapes
+// newlines inside strings, so a document can never span lines, and appending
+// one encoder's output to another's still yields valid NDJSON.
+pub struct NdjsonEncoder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonEncoder<W> {
+    pub fn new(writer: W) -> NdjsonEncoder<W> {
+        NdjsonEncoder { writer }
+    }
+
+    // returns the number of bytes written, including the newline
+    pub fn encode<T: Serialize>(&mut self, doc: &T) -> io::Result<u64> {
+        let mut line = serde_json::to_vec(doc)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        Ok(line.len() as u64)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,191 @@
+use std;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::warc::DocJson;
+
+// Words per shingle of the SimHash.
+const SHINGLE_WORDS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NearDupMode {
+    // drop documents close to one seen before
+    Drop,
+    // keep them, with `duplicate_of` set to the signature of the first one
+    Tag,
+}
+
+impl NearDupMode {
+    pub fn parse(name: &str) -> Option<NearDupMode> {
+        match name {
+            "drop" => Some(NearDupMode::Drop),
+            "tag" => Some(NearDupMode::Tag),
+            _ => None,
+        }
+    }
+}
+
+// FNV-1a followed by the splitmix64 finalizer. Signatures end up in indexes
+// and parquet files, so unlike `DefaultHasher` this must never change.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+// The 64 bit SimHash of the lower cased word shingles of `text`. Texts that
+// differ in a few words have signatures that differ in a few bits.
+pub fn simhash(text: &str) -> u64 {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    let mut counts = [0i64; 64];
+    let mut add = |shingle: &[String]| {
+        let h = hash(shingle.join(" ").as_bytes());
+        for (bit, count) in counts.iter_mut().enumerate() {
+            if h & (1 << bit) != 0 {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        }
+    };
+    if words.len() < SHINGLE_WORDS {
+        add(&words);
+    } else {
+        for shingle in words.windows(SHINGLE_WORDS) {
+            add(shingle);
+        }
+    }
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .fold(0, |signature, (bit, _)| signature | 1 << bit)
+}
+
+// The signatures seen so far by all tasks of a run. They are split into
+// `max_distance + 1` blocks of bits: two signatures that differ in at most
+// `max_distance` bits agree on at least one block, so only signatures sharing
+// a block with the new one need to be compared.
+#[derive(Debug)]
+pub struct NearDup {
+    pub mode: NearDupMode,
+    pub max_distance: u32,
+    blocks: Vec<(u32, u64)>,
+    seen: Mutex<Vec<HashMap<u64, Vec<u64>>>>,
+}
+
+impl NearDup {
+    pub fn new(mode: NearDupMode, max_distance: u32) -> NearDup {
+        let count = (max_distance + 1).min(64);
+        let blocks = (0..count)
+            .map(|i| {
+                let from = i * 64 / count;
+                let to = (i + 1) * 64 / count;
+                let mask = if to - from == 64 {
+                    u64::MAX
+                } else {
+                    ((1u64 << (to - from)) - 1) << from
+                };
+                (from, mask)
+            })
+            .collect();
+        NearDup {
+            mode,
+            max_distance,
+            blocks,
+            seen: Mutex::new(vec![HashMap::new(); count as usize]),
+        }
+    }
+
+    // Returns the signature of an earlier document within `max_distance` bits
+    // of `signature`, or remembers `signature` if there is none.
+    pub fn check(&self, signature: u64) -> Option<u64> {
+        let mut seen = self.seen.lock().expect("near duplicate lock");
+        for ((shift, mask), table) in self.blocks.iter().zip(seen.iter()) {
+            let key = (signature & mask) >> shift;
+            if let Some(candidates) = table.get(&key) {
+                if let Some(first) = candidates
+                    .iter()
+                    .find(|other| (signature ^ **other).count_ones() <= self.max_distance)
+                {
+                    return Some(*first);
+                }
+            }
+        }
+        for ((shift, mask), table) in self.blocks.iter().zip(seen.iter_mut()) {
+            let key = (signature & mask) >> shift;
+            table.entry(key).or_insert_with(Vec::new).push(signature);
+        }
+        None
+    }
+
+    // Sets the signature of `doc` and returns false if it is to be dropped.
+    pub fn process(&self, doc: &mut DocJson) -> bool {
+        let signature = simhash(&doc.body);
+        doc.simhash = Some(signature);
+        match (self.check(signature), self.mode) {
+            (Some(_), NearDupMode::Drop) => false,
+            (duplicate_of, _) => {
+                doc.duplicate_of = duplicate_of;
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: &str, b: &str) -> u32 {
+        (simhash(a) ^ simhash(b)).count_ones()
+    }
+
+    #[test]
+    fn simhash_distance() {
+        let page: String = (0..40)
+            .map(|i| format!("Paragraph {} of a long article about foxes and dogs. ", i))
+            .collect();
+        // case and punctuation do not matter
+        assert_eq!(distance(&page, &page.to_uppercase().replace('.', " ,")), 0);
+        assert!(distance(&page, &format!("{} today", page)) <= 3);
+        let unrelated = "Completely unrelated text about compilers, parsers and type checkers";
+        assert!(distance(&page, unrelated) > 3);
+        // texts shorter than a shingle are one shingle
+        assert_eq!(distance("two words", "Two, words!"), 0);
+        // signatures are stored, so they must not change
+        assert_eq!(
+            simhash("The quick brown fox jumps over the lazy dog"),
+            0x0d2d_bd69_8907_3266
+        );
+    }
+
+    #[test]
+    fn blocks_find_close_signatures() {
+        let near_dup = NearDup::new(NearDupMode::Tag, 3);
+        let first = 0x0123_4567_89ab_cdef;
+        assert_eq!(near_dup.check(first), None);
+        // three bits in three of the four blocks
+        assert_eq!(near_dup.check(first ^ (1 | 1 << 20 | 1 << 40)), Some(first));
+        // four bits, one in every block, is too far
+        let far = first ^ (1 | 1 << 20 | 1 << 40 | 1 << 60);
+        assert_eq!(near_dup.check(far), None);
+        // and remembered
+        assert_eq!(near_dup.check(far ^ 1 << 63), Some(far));
+
+        // a single block only finds equal signatures
+        let exact = NearDup::new(NearDupMode::Drop, 0);
+        assert_eq!(exact.check(5), None);
+        assert_eq!(exact.check(5), Some(5));
+        assert_eq!(exact.check(4), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::chunk::Chunker;
+use crate::decode;
+use crate::decode::{Decoding, Repairs};
+use crate::embed::Embedding;
+use crate::exact_dup;
+use crate::exact_dup::SeenSet;
+use crate::lang;
+use crate::near_dup::NearDup;
+use crate::quality;
+use crate::quality::QualityFilter;
+use crate::url_filter::UrlFilter;
+use crate::urls::UrlParts;
+use crate::warc::DocJson;
+
+// Counters of all tasks of a run, printed as the run summary.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    pub processed: AtomicU64,
+    pub kept: AtomicU64,
+    pub exact_duplicates: AtomicU64,
+    pub near_duplicates: AtomicU64,
+    // passages the documents kept were split into
+    pub passages: AtomicU64,
+    // records with invalid byte sequences, replaced by U+FFFD
+    pub lossy_decoded: AtomicU64,
+    // records in another charset than UTF-8
+    pub recoded: AtomicU64,
+    // documents with mojibake undone or not in NFC, see `decode::repair`
+    pub mojibake_repaired: AtomicU64,
+    pub normalized: AtomicU64,
+    // documents dropped per filter, by the filter's name
+    pub filtered: Mutex<BTreeMap<&'static str, u64>>,
+    // records skipped for lacking `WARC-Target-URI` or `WARC-Date`, or PubMed
+    // articles without citation
+    pub incomplete_records: AtomicU64,
+    // input files that could not be opened or not be processed to the end
+    pub failed_files: AtomicU64,
+}
+
+impl RunStats {
+    pub fn count_repairs(&self, repairs: Repairs) {
+        if repairs.mojibake {
+            self.mojibake_repaired.fetch_add(1, Ordering::Relaxed);
+        }
+        if repairs.normalized {
+            self.normalized.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count_filtered(&self, filter: &'static str) {
+        let mut filtered = self.filtered.lock().expect("run stats lock");
+        *filtered.entry(filter).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let processed = self.processed.load(Ordering::Relaxed);
+        let ratio = |count: u64| {
+            let percent = if processed == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / processed as f64
+            };
+            format!("{} ({:.2}%)", count, percent)
+        };
+        writeln!(f, "Documents processed: {}", processed)?;
+        let load = |count: &AtomicU64| ratio(count.load(Ordering::Relaxed));
+        writeln!(f, "Documents kept: {}", load(&self.kept))?;
+        for (filter, count) in self.filtered.lock().expect("run stats lock").iter() {
+            writeln!(f, "Filtered by {}: {}", filter, ratio(*count))?;
+        }
+        writeln!(f, "Exact duplicates: {}", load(&self.exact_duplicates))?;
+        write!(f, "Near duplicates: {}", load(&self.near_duplicates))?;
+        let repairs = [
+            ("Decoded lossily", &self.lossy_decoded),
+            ("Decoded from other charsets", &self.recoded),
+            ("Mojibake repaired", &self.mojibake_repaired),
+            ("Normalized to NFC", &self.normalized),
+        ];
+        for (name, count) in repairs.iter() {
+            if count.load(Ordering::Relaxed) > 0 {
+                write!(f, "\n{}: {}", name, load(count))?;
+            }
+        }
+        if self.passages.load(Ordering::Relaxed) > 0 {
+            write!(f, "\nPassages: {}", self.passages.load(Ordering::Relaxed))?;
+        }
+        if self.incomplete_records.load(Ordering::Relaxed) > 0 {
+            let incomplete = self.incomplete_records.load(Ordering::Relaxed);
+            write!(f, "\nIncomplete records skipped: {}", incomplete)?;
+        }
+        match self.failed_files.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            failed => write!(f, "\nFailed files: {}", failed),
+        }
+    }
+}
+
+// Per-document processing between parsing and the sink, shared by all source
+// types and all tasks of a run.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    // skip records by their URI before anything else is done with them
+    pub urls: Option<Arc<UrlFilter>>,
+    // only keep documents in one of these ISO 639-3 languages
+    pub languages: Option<Arc<HashSet<String>>>,
+    // drop documents failing one of these, in order
+    pub quality: Option<Arc<Vec<QualityFilter>>>,
+    // drop documents with a body seen before
+    pub seen: Option<Arc<SeenSet>>,
+    pub near_dup: Option<Arc<NearDup>>,
+    // split every document kept into passages, batch by batch
+    pub chunker: Option<Arc<Chunker>>,
+    // add an embedding to every document or passage kept
+    pub embedding: Option<Arc<Embedding>>,
+    pub stats: Arc<RunStats>,
+    // path of the input file, recorded as the documents' `source_file`
+    pub source_file: Option<String>,
+    // id of the input file in `seen`, set by `for_input`
+    input: u32,
+}
+
+// The stages a document passes, by the option enabling them, for the log of
+// a run.
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stages = [
+            ("url-rules", self.urls.is_some()),
+            ("lang", self.languages.is_some()),
+            ("quality", self.quality.is_some()),
+            ("dedup", self.seen.is_some()),
+            ("near-dup", self.near_dup.is_some()),
+            ("chunk", self.chunker.is_some()),
+            ("embed", self.embedding.is_some()),
+        ];
+        let enabled: Vec<&str> = stages
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect();
+        if enabled.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&enabled.join(", "))
+        }
+    }
+}
+
+impl Pipeline {
+    // The pipeline for the task processing the input file `name`.
+    pub fn for_input(&self, name: &str) -> io::Result<Pipeline> {
+        let mut pipeline = self.clone();
+        if let Some(seen) = &self.seen {
+            pipeline.input = seen.input_id(name)?;
+        }
+        Ok(pipeline)
+    }
+
+    // Returns false if the record with `uri` is to be skipped, before its
+    // text is decoded or a document built from it.
+    pub fn accepts_uri(&self, uri: &str) -> bool {
+        if let Some(urls) = &self.urls {
+            if let Some(reason) = urls.check(uri) {
+                self.stats.processed.fetch_add(1, Ordering::Relaxed);
+                self.stats.count_filtered(reason);
+                return false;
+            }
+        }
+        true
+    }
+
+    // Counts how the text of a record was decoded, see `decode::decode`.
+    pub fn count_decoding(&self, decoding: &Decoding) {
+        if decoding.lossy {
+            self.stats.lossy_decoded.fetch_add(1, Ordering::Relaxed);
+        }
+        if decoding.encoding != encoding_rs::UTF_8 {
+            self.stats.recoded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Returns false if the document is to be dropped.
+    pub fn process(&self, doc: &mut DocJson) -> io::Result<bool> {
+        self.stats.processed.fetch_add(1, Ordering::Relaxed);
+        let repairs = decode::repair(&mut doc.title).or(decode::repair(&mut doc.body));
+        self.stats.count_repairs(repairs);
+        if doc.source_file.is_none() {
+            doc.source_file = self.source_file.clone();
+        }
+        if let Some(parts) = UrlParts::parse(&doc.uri) {
+            doc.scheme = Some(parts.scheme);
+            doc.host = Some(parts.host);
+            doc.domain = parts.domain;
+            doc.tld = parts.tld;
+        }
+        if doc.language.is_none() {
+            doc.language = lang::detect(&doc.body);
+        }
+        if let Some(languages) = &self.languages {
+            match &doc.language {
+                Some(language) if languages.contains(language) => (),
+                _ => {
+                    self.stats.count_filtered("lang");
+                    return Ok(false);
+                }
+            }
+        }
+        if let Some(filters) = &self.quality {
+            if let Some(filter) = quality::check(filters, &doc.body, doc.language.as_deref()) {
+                self.stats.count_filtered(filter);
+                return Ok(false);
+            }
+        }
+        let hash = exact_dup::content_hash(&doc.body);
+        doc.content_hash = Some(exact_dup::to_hex(hash));
+        if let Some(seen) = &self.seen {
+            if seen.check(hash, self.input)? {
+                self.stats.exact_duplicates.fetch_add(1, Ordering::Relaxed);
+                return Ok(false);
+            }
+        }
+        if let Some(near_dup) = &self.near_dup {
+            let keep = near_dup.process(doc);
+            if !keep || doc.duplicate_of.is_some() {
+                self.stats.near_duplicates.fetch_add(1, Ordering::Relaxed);
+            }
+            if !keep {
+                return Ok(false);
+            }
+        }
+        self.stats.kept.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    // Runs the batch level stages on the documents kept, right before they
+    // are handed to the sink: splits them into passages, then embeds those.
+    pub fn prepare_batch(&self, batch: &mut Vec<DocJson>) -> io::Result<()> {
+        if let Some(chunker) = &self.chunker {
+            let passages: Vec<DocJson> =
+                batch.drain(..).flat_map(|doc| chunker.chunk(doc)).collect();
+            self.stats
+                .passages
+                .fetch_add(passages.len() as u64, Ordering::Relaxed);
+            *batch = passages;
+        }
+        match &self.embedding {
+            Some(embedding) => embedding.embed(batch),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.seen {
+            Some(seen) => seen.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -2,84 +2,132 @@ use std;
 use std::io;
 use std::io::BufRead;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 
 extern crate tantivy;
 use crate::warc::DocJsonBuilder;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio::io::BufWriter;
+
+use crate::dates;
+use crate::decode;
+use crate::pipeline::Pipeline;
+use crate::sink;
+use crate::sink::OutputOptions;
 
 use entrez_rs::parser::pubmed::PubmedArticleSet;
 
+fn invalid_data<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+}
+
 pub async fn extract_records_and_add_to_json(
     mut reader: impl BufRead + Send,
     path: PathBuf,
+    output: &OutputOptions,
+    pipeline: &Pipeline,
 ) -> io::Result<()> {
     let out_file_path = path.with_extension("wka.json");
-    let out_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&out_file_path)
-        .await?;
-    let mut writer = BufWriter::new(out_file);
+    let mut sink = sink::create(out_file_path, output, "ENTREZ")?;
+    let batch_size = output.batch_size.unwrap_or(1000);
 
-    let mut doc = String::new();
-    reader.read_to_string(&mut doc).expect("read file");
-    let pm_parsed = PubmedArticleSet::read(&doc);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let charset = decode::xml_charset(&bytes);
+    let (doc, decoding) = decode::decode(&bytes, charset.as_deref());
+    if decoding.lossy {
+        eprintln!(
+            "Invalid {} in {}, replaced by U+FFFD",
+            decoding.encoding.name(),
+            path.to_string_lossy()
+        );
+    }
+    let pm_parsed = PubmedArticleSet::read(&doc).map_err(invalid_data)?;
     let mut count = 0;
     let mut batch = Vec::new();
-    for pubmed_article in pm_parsed.expect("parsed").articles {
+    for pubmed_article in pm_parsed.articles {
         count += 1;
         if count % 1000 == 0 {
             eprint!(".");
         }
 
-        if batch.len() > 1000 {
-            let docs = batch
-                .iter()
-                .map(|doc| serde_json::to_string(&doc).unwrap())
-                .collect::<Vec<String>>();
-            // join on newline
-            let blob = docs.join("\n");
-            let blob = blob.as_bytes();
-            writer.write(blob).await.unwrap();
-            writer.flush().await.unwrap();
+        if batch.len() >= batch_size {
+            pipeline.prepare_batch(&mut batch)?;
+            sink.write_batch(&batch)?;
             batch.clear();
         }
 
         let mut doc = DocJsonBuilder::default();
-        let article = pubmed_article
-            .medline_citation
-            .expect("medline_citation")
-            .article
-            .expect("article");
+        // without citation or article there is nothing to index
+        let citation = match pubmed_article.medline_citation {
+            Some(citation) => citation,
+            None => {
+                pipeline
+                    .stats
+                    .incomplete_records
+                    .fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        let pmid = citation.pmid.map(|pmid| pmid.to_string());
+        let article = match citation.article {
+            Some(article) => article,
+            None => {
+                pipeline
+                    .stats
+                    .incomplete_records
+                    .fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        let pub_date = article
+            .journal
+            .and_then(|journal| journal.journal_issue)
+            .and_then(|issue| issue.pub_date);
+        // a <MedlineDate> stands in for year, month and day if they do not fit
+        let date = pub_date.and_then(|date| {
+            dates::from_pubmed_date(
+                date.year.as_deref(),
+                date.month.as_deref(),
+                date.day.as_deref(),
+            )
+            .or_else(|| {
+                date.medline_date
+                    .as_deref()
+                    .and_then(dates::from_medline_date)
+            })
+        });
         if let Some(title) = article.title {
             doc.title(title);
         }
+        // structured abstracts have a section per `<AbstractText>`
         if let Some(abstract_text) = article.abstract_text {
-            for text in abstract_text.text {
-                if let Some(value) = text.value {
-                    doc.body(value);
-                }
+            let sections: Vec<String> = abstract_text
+                .text
+                .into_iter()
+                .filter_map(|text| text.value)
+                .collect();
+            doc.body(sections.join("\n"));
+        }
+        doc.date(date.unwrap_or_default());
+        match pmid {
+            Some(pmid) => {
+                doc.uri(format!("https://pubmed.ncbi.nlm.nih.gov/{}/", pmid));
+                doc.record_id(Some(pmid));
             }
+            None => {
+                doc.uri("".into());
+            }
+        }
+        let mut doc = doc.build().map_err(invalid_data)?;
+        if pipeline.accepts_uri(&doc.uri) && pipeline.process(&mut doc)? {
+            batch.push(doc);
         }
-        doc.date("".into());
-        doc.uri("".into());
-        batch.push(doc.build().unwrap());
     }
     if batch.len() > 0 {
-        let docs = batch
-            .iter()
-            .map(|doc| serde_json::to_string(&doc).unwrap())
-            .collect::<Vec<String>>();
-        // join on newline
-        let blob = docs.join("\n");
-        let blob = blob.as_bytes();
-        writer.write(blob).await.unwrap();
-        writer.flush().await.unwrap();
+        pipeline.prepare_batch(&mut batch)?;
+        sink.write_batch(&batch)?;
         batch.clear();
     }
+    sink.finish()?;
     println!("\nTotal Records of WARC file processed: {}", count);
     Ok(())
 }
@@ -0,0 +1,292 @@
+use std;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::segment;
+
+// Gopher's rule of thumb: real English text has at least two of these.
+const STOPWORDS: &[&str] = &["the", "be", "to", "of", "and", "that", "have", "with"];
+
+// One rule of the quality filter chain, named like its `--quality` option.
+#[derive(Debug, Clone)]
+pub enum QualityFilter {
+    MinWords(usize),
+    MaxWords(usize),
+    // alphabetic characters among all non-whitespace characters
+    MinAlphaRatio(f64),
+    // non-empty lines that repeat an earlier line among all non-empty lines
+    MaxLineRepetition(f64),
+    MinMeanLineLength(f64),
+    MinSentences(usize),
+    // lines ending in `.`, `!`, `?` or a closing quote among all non-empty
+    // lines, low for menus, link lists and other boilerplate
+    MinPunctuatedLines(f64),
+    // only applied to English or undetected text
+    MinStopwords(usize),
+    // drops documents containing any of the lower cased words
+    BadWords(HashSet<String>),
+}
+
+// What the filters look at, computed once per document.
+struct TextStats<'a> {
+    words: Vec<String>,
+    lines: Vec<&'a str>,
+    sentences: usize,
+    chars: usize,
+    alphabetic: usize,
+}
+
+impl<'a> TextStats<'a> {
+    fn new(text: &'a str) -> TextStats<'a> {
+        let words = text
+            .split_whitespace()
+            .map(|word| {
+                word.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .filter(|word| !word.is_empty())
+            .collect();
+        let lines = segment::paragraphs(text)
+            .into_iter()
+            .map(|line| &text[line])
+            .collect();
+        let sentences = segment::sentences(text).len();
+        let chars = text.chars().filter(|c| !c.is_whitespace()).count();
+        let alphabetic = text.chars().filter(|c| c.is_alphabetic()).count();
+        TextStats {
+            words,
+            lines,
+            sentences,
+            chars,
+            alphabetic,
+        }
+    }
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+impl QualityFilter {
+    // Parses a `name=value` rule, see the README for the names.
+    pub fn parse(rule: &str) -> Result<QualityFilter, String> {
+        let (name, value) = match rule.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return Err(format!("quality rule {} has no value", rule)),
+        };
+        let count = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("quality rule {} needs a count", name))
+        };
+        let fraction = || match value.parse::<f64>() {
+            Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+            _ => Err(format!("quality rule {} needs a ratio from 0 to 1", name)),
+        };
+        let filter = match name {
+            "min-words" => QualityFilter::MinWords(count()?),
+            "max-words" => QualityFilter::MaxWords(count()?),
+            "min-alpha-ratio" => QualityFilter::MinAlphaRatio(fraction()?),
+            "max-line-repetition" => QualityFilter::MaxLineRepetition(fraction()?),
+            "min-mean-line-length" => QualityFilter::MinMeanLineLength(
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("quality rule {} needs a length", name))?,
+            ),
+            "min-sentences" => QualityFilter::MinSentences(count()?),
+            "min-punctuated-lines" => QualityFilter::MinPunctuatedLines(fraction()?),
+            "min-stopwords" => QualityFilter::MinStopwords(count()?),
+            _ => return Err(format!("unknown quality rule {}", name)),
+        };
+        Ok(filter)
+    }
+
+    // Reads the bad words, one per line, from `path`.
+    pub fn bad_words(path: &Path) -> io::Result<QualityFilter> {
+        let words = fs::read_to_string(path)?
+            .lines()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Ok(QualityFilter::BadWords(words))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QualityFilter::MinWords(_) => "min-words",
+            QualityFilter::MaxWords(_) => "max-words",
+            QualityFilter::MinAlphaRatio(_) => "min-alpha-ratio",
+            QualityFilter::MaxLineRepetition(_) => "max-line-repetition",
+            QualityFilter::MinMeanLineLength(_) => "min-mean-line-length",
+            QualityFilter::MinSentences(_) => "min-sentences",
+            QualityFilter::MinPunctuatedLines(_) => "min-punctuated-lines",
+            QualityFilter::MinStopwords(_) => "min-stopwords",
+            QualityFilter::BadWords(_) => "bad-words",
+        }
+    }
+
+    fn keep(&self, text: &TextStats, language: Option<&str>) -> bool {
+        match self {
+            QualityFilter::MinWords(min) => text.words.len() >= *min,
+            QualityFilter::MaxWords(max) => text.words.len() <= *max,
+            QualityFilter::MinAlphaRatio(min) => ratio(text.alphabetic, text.chars) >= *min,
+            QualityFilter::MaxLineRepetition(max) => {
+                let mut seen = HashSet::new();
+                let repeated = text.lines.iter().filter(|line| !seen.insert(*line)).count();
+                ratio(repeated, text.lines.len()) <= *max
+            }
+            QualityFilter::MinMeanLineLength(min) => {
+                let length: usize = text.lines.iter().map(|line| line.chars().count()).sum();
+                !text.lines.is_empty() && length as f64 / text.lines.len() as f64 >= *min
+            }
+            QualityFilter::MinSentences(min) => text.sentences >= *min,
+            QualityFilter::MinPunctuatedLines(min) => {
+                let punctuated = text
+                    .lines
+                    .iter()
+                    .filter(|line| line.ends_with(['.', '!', '?', '"', '”', '。', '！', '？']))
+                    .count();
+                ratio(punctuated, text.lines.len()) >= *min
+            }
+            QualityFilter::MinStopwords(min) => match language {
+                None | Some("eng") => {
+                    let found = STOPWORDS
+                        .iter()
+                        .filter(|stopword| text.words.iter().any(|word| word == *stopword))
+                        .count();
+                    found >= *min
+                }
+                Some(_) => true,
+            },
+            QualityFilter::BadWords(words) => !text.words.iter().any(|word| words.contains(word)),
+        }
+    }
+}
+
+// Returns the name of the first filter of `filters` that drops `body`.
+pub fn check(
+    filters: &[QualityFilter],
+    body: &str,
+    language: Option<&str>,
+) -> Option<&'static str> {
+    if filters.is_empty() {
+        return None;
+    }
+    let text = TextStats::new(body);
+    filters
+        .iter()
+        .find(|filter| !filter.keep(&text, language))
+        .map(|filter| filter.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The name of the filter of `rule` if it drops `body` of unknown language.
+    fn drops(rule: &str, body: &str) -> Option<&'static str> {
+        check(&[QualityFilter::parse(rule).unwrap()], body, None)
+    }
+
+    #[test]
+    fn rules_parsed() {
+        let filter = QualityFilter::parse(" min-words = 5 ").unwrap();
+        assert_eq!(filter.name(), "min-words");
+        let invalid = [
+            "min-words",
+            "min-words=five",
+            "min-alpha-ratio=2",
+            "max-line-repetition=-0.1",
+            "min-mean-line-length=long",
+            "no-such-rule=1",
+        ];
+        for rule in invalid.iter() {
+            assert!(QualityFilter::parse(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(drops("min-words=3", "one two three"), None);
+        // punctuation on its own is no word
+        assert_eq!(drops("min-words=3", "one two —"), Some("min-words"));
+        assert_eq!(drops("max-words=2", "one two three"), Some("max-words"));
+        assert_eq!(drops("min-alpha-ratio=0.5", "abc 12"), None);
+        assert_eq!(
+            drops("min-alpha-ratio=0.5", "ab 123"),
+            Some("min-alpha-ratio")
+        );
+        assert_eq!(drops("max-line-repetition=0.25", "a\nb\n a \nc"), None);
+        assert_eq!(
+            drops("max-line-repetition=0.25", "a\na\na\nb"),
+            Some("max-line-repetition")
+        );
+        assert_eq!(drops("min-mean-line-length=5", "abcde\n\nabcde"), None);
+        assert_eq!(
+            drops("min-mean-line-length=5", "abcdef\nab"),
+            Some("min-mean-line-length")
+        );
+        assert_eq!(drops("min-sentences=2", "One. Two."), None);
+        // the `.` of an abbreviation ends no sentence
+        assert_eq!(
+            drops("min-sentences=2", "Dr. Smith left."),
+            Some("min-sentences")
+        );
+        assert_eq!(
+            drops("min-punctuated-lines=0.5", "Menu\nA sentence.\n\"Quoted.\""),
+            None
+        );
+        assert_eq!(
+            drops(
+                "min-punctuated-lines=0.5",
+                "Home\nAbout\nContact\nA sentence."
+            ),
+            Some("min-punctuated-lines")
+        );
+        // empty bodies fail every minimum
+        assert_eq!(drops("min-alpha-ratio=0.1", ""), Some("min-alpha-ratio"));
+        assert_eq!(
+            drops("min-mean-line-length=1", ""),
+            Some("min-mean-line-length")
+        );
+    }
+
+    #[test]
+    fn stopwords_only_for_english() {
+        let filters = [QualityFilter::parse("min-stopwords=2").unwrap()];
+        assert_eq!(check(&filters, "The cat and the dog", None), None);
+        assert_eq!(check(&filters, "cat dog", None), Some("min-stopwords"));
+        assert_eq!(
+            check(&filters, "cat dog", Some("eng")),
+            Some("min-stopwords")
+        );
+        assert_eq!(check(&filters, "Katze Hund", Some("deu")), None);
+    }
+
+    #[test]
+    fn bad_words_matched_as_words() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad-words.txt");
+        fs::write(&path, "Spam\n\n  eggs \n").unwrap();
+        let filters = [QualityFilter::bad_words(&path).unwrap()];
+        assert_eq!(check(&filters, "Buy SPAM, now", None), Some("bad-words"));
+        assert_eq!(check(&filters, "green eggs.", None), Some("bad-words"));
+        assert_eq!(check(&filters, "spammy eggplant", None), None);
+    }
+
+    #[test]
+    fn first_failing_filter_named() {
+        let words = QualityFilter::parse("min-words=2").unwrap();
+        let alpha = QualityFilter::parse("min-alpha-ratio=0.9").unwrap();
+        let filters = [words.clone(), alpha.clone()];
+        assert_eq!(check(&filters, "12", None), Some("min-words"));
+        assert_eq!(check(&[alpha, words], "12", None), Some("min-alpha-ratio"));
+        assert_eq!(check(&[], "", None), None);
+    }
+}
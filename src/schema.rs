@@ -0,0 +1,391 @@
+use std;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use tantivy::schema::{
+    Facet, Field, IndexRecordOption, Schema, SchemaBuilder, TextFieldIndexing, TextOptions, FAST,
+    INDEXED, STORED, STRING,
+};
+use tantivy::Document;
+use tantivy::Index;
+
+use crate::dates;
+use crate::lang;
+use crate::tokenizers;
+use crate::tokenizers::LANGUAGE_TOKENIZERS;
+use crate::warc::DocJson;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceType {
+    Warc,
+    WikipediaAbstract,
+    Entrez,
+}
+
+impl SourceType {
+    pub fn parse(name: &str) -> Option<SourceType> {
+        match name {
+            "WARC" => Some(SourceType::Warc),
+            "WIKIPEDIA_ABSTRACT" => Some(SourceType::WikipediaAbstract),
+            "ENTREZ" => Some(SourceType::Entrez),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SourceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SourceType::Warc => "WARC",
+            SourceType::WikipediaAbstract => "WIKIPEDIA_ABSTRACT",
+            SourceType::Entrez => "ENTREZ",
+        };
+        f.write_str(name)
+    }
+}
+
+// How much of the body the index keeps besides the postings, `--store-body`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyStore {
+    // the whole text in the body field
+    Full,
+    // the whole text zstd compressed in `body_zstd`
+    Zstd,
+    // the first chars of the text in `body_excerpt`
+    Truncate(usize),
+    // nothing, snippets re-read WARC records at `source_file`/`source_offset`
+    None,
+}
+
+impl Default for BodyStore {
+    fn default() -> Self {
+        BodyStore::Full
+    }
+}
+
+impl BodyStore {
+    pub fn parse(name: &str) -> Option<BodyStore> {
+        match name {
+            "" | "full" => Some(BodyStore::Full),
+            "zstd" => Some(BodyStore::Zstd),
+            "none" => Some(BodyStore::None),
+            _ => {
+                let chars = name.strip_prefix("truncate=")?.parse::<usize>().ok()?;
+                Some(BodyStore::Truncate(chars)).filter(|_| chars > 0)
+            }
+        }
+    }
+}
+
+// What a new index holds besides the fields every document of its source has.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SchemaOptions {
+    // per-language `title_<lang>` and `body_<lang>` fields, `--multilingual`
+    pub multilingual: bool,
+    pub body_store: BodyStore,
+    // the fields of passages, `--chunk`
+    pub passages: bool,
+    // tokenize bodies sentence by sentence, `--sentence-positions`
+    pub sentence_positions: bool,
+}
+
+fn text_options(tokenizer: &str, stored: bool) -> TextOptions {
+    let options = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(tokenizer)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    if stored {
+        options.set_stored()
+    } else {
+        options
+    }
+}
+
+// Raw terms for filtering, e.g. `tld:gov`, and the `/tld/domain/host` facet
+// for counting documents per top level domain, domain or host.
+fn add_url_fields(builder: &mut SchemaBuilder) {
+    builder.add_text_field("scheme", STRING | STORED);
+    builder.add_text_field("host", STRING | STORED);
+    builder.add_text_field("domain", STRING | STORED);
+    builder.add_text_field("tld", STRING | STORED);
+    builder.add_facet_field("site", INDEXED);
+}
+
+// The schema for documents of `source`. All sources share `uri`, `title`,
+// `body`, `date`, `language`, `record_id` and the content hash fields, WARC
+// files add their record metadata and sources with URLs the parts of the URL.
+// With `multilingual` every language of `LANGUAGE_TOKENIZERS` additionally
+// gets a `title_<lang>` and `body_<lang>` pair using that language's tokenizer.
+// Bodies are only stored in full with `BodyStore::Full`, otherwise `body_store`
+// adds the field holding what is kept of them and WARC files the record's
+// position in its input file. With `passages` documents are passages of the
+// document `parent_id`, see `chunk`. With `sentence_positions` the body fields
+// use the `SentenceTokenizer` variant of their tokenizer.
+pub fn build_schema(source: SourceType, options: SchemaOptions) -> Schema {
+    let SchemaOptions {
+        multilingual,
+        body_store,
+        passages,
+        sentence_positions,
+    } = options;
+    let store_body = body_store == BodyStore::Full;
+    let body_tokenizer = |tokenizer: &str| {
+        if sentence_positions {
+            tokenizers::sentences(tokenizer)
+        } else {
+            tokenizer.to_string()
+        }
+    };
+    let mut builder = Schema::builder();
+    builder.add_text_field("uri", STRING | STORED);
+    builder.add_text_field("title", text_options("en_stem", true));
+    builder.add_text_field("body", text_options(&body_tokenizer("en_stem"), store_body));
+    builder.add_date_field("date", INDEXED | STORED | FAST);
+    builder.add_text_field("language", STRING | STORED);
+    builder.add_text_field("record_id", STRING | STORED);
+    builder.add_text_field("content_hash", STRING | STORED);
+    builder.add_u64_field("simhash", INDEXED | STORED | FAST);
+    builder.add_u64_field("duplicate_of", INDEXED | STORED | FAST);
+    match source {
+        SourceType::Warc => {
+            builder.add_u64_field("content_length", INDEXED | STORED | FAST);
+            builder.add_text_field("block_digest", STRING | STORED);
+            add_url_fields(&mut builder);
+            if !store_body {
+                builder.add_text_field("source_file", STORED);
+                builder.add_u64_field("source_offset", STORED);
+            }
+        }
+        SourceType::WikipediaAbstract => {
+            add_url_fields(&mut builder);
+        }
+        SourceType::Entrez => (),
+    }
+    match body_store {
+        BodyStore::Full | BodyStore::None => (),
+        BodyStore::Zstd => {
+            builder.add_bytes_field("body_zstd", STORED);
+        }
+        BodyStore::Truncate(_) => {
+            builder.add_text_field("body_excerpt", STORED);
+        }
+    }
+    if passages {
+        builder.add_text_field("parent_id", STRING | STORED);
+        builder.add_u64_field("passage", INDEXED | STORED | FAST);
+        builder.add_u64_field("passage_start", STORED);
+        builder.add_u64_field("passage_end", STORED);
+    }
+    if multilingual {
+        for (language, tokenizer) in LANGUAGE_TOKENIZERS {
+            let title = format!("title_{}", language);
+            builder.add_text_field(&title, text_options(tokenizer, true));
+            let body = format!("body_{}", language);
+            builder.add_text_field(&body, text_options(&body_tokenizer(tokenizer), store_body));
+        }
+    }
+    builder.build()
+}
+
+// Lists every field of `expected` that `existing` lacks or defines differently.
+// Additional fields in `existing` are fine, they are just not filled.
+pub fn mismatches(existing: &Schema, expected: &Schema) -> Vec<String> {
+    let mut report = Vec::new();
+    for (_, expected_entry) in expected.fields() {
+        let name = expected_entry.name();
+        match existing.get_field(name) {
+            None => report.push(format!("missing field {}", name)),
+            Some(field) => {
+                let existing_entry = existing.get_field_entry(field);
+                if existing_entry != expected_entry {
+                    report.push(format!(
+                        "field {} is {} but should be {}",
+                        name,
+                        serde_json::to_string(existing_entry).unwrap_or_default(),
+                        serde_json::to_string(expected_entry).unwrap_or_default(),
+                    ));
+                }
+            }
+        }
+    }
+    report
+}
+
+// Opens the index in `dir`, creating the directory and the index with
+// `expected` if there is none yet. An existing index must have all fields of
+// `expected`, otherwise the error lists every mismatch.
+pub fn open_or_create_index(dir: &Path, expected: &Schema) -> io::Result<Index> {
+    let to_io_error = |e: tantivy::TantivyError| io::Error::new(io::ErrorKind::Other, e);
+    if !dir.join("meta.json").exists() {
+        std::fs::create_dir_all(dir)?;
+        eprintln!("Creating new index in {}", dir.to_string_lossy());
+        return Index::create_in_dir(dir, expected.clone()).map_err(to_io_error);
+    }
+    let index = Index::open_in_dir(dir).map_err(to_io_error)?;
+    let report = mismatches(&index.schema(), expected);
+    if !report.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "schema of index {} does not match:\n  {}",
+                dir.to_string_lossy(),
+                report.join("\n  ")
+            ),
+        ));
+    }
+    Ok(index)
+}
+
+// Chars kept in `body_excerpt` unless set with `BodyStore::Truncate`.
+const DEFAULT_EXCERPT_CHARS: usize = 1000;
+
+// The fields documents are written to, resolved once per index.
+#[derive(Debug, Clone)]
+pub struct DocFields {
+    schema: Schema,
+    pub uri: Field,
+    pub title: Field,
+    pub body: Field,
+    pub date: Field,
+    pub language: Option<Field>,
+    pub record_id: Option<Field>,
+    pub host: Option<Field>,
+    pub scheme: Option<Field>,
+    pub domain: Option<Field>,
+    pub tld: Option<Field>,
+    pub site: Option<Field>,
+    pub content_length: Option<Field>,
+    pub content_hash: Option<Field>,
+    pub block_digest: Option<Field>,
+    pub simhash: Option<Field>,
+    pub duplicate_of: Option<Field>,
+    pub body_zstd: Option<Field>,
+    pub body_excerpt: Option<Field>,
+    pub source_file: Option<Field>,
+    pub source_offset: Option<Field>,
+    pub parent_id: Option<Field>,
+    pub passage: Option<Field>,
+    pub passage_start: Option<Field>,
+    pub passage_end: Option<Field>,
+    // chars of the body kept in `body_excerpt`
+    pub excerpt_chars: usize,
+}
+
+impl DocFields {
+    pub fn resolve(schema: &Schema) -> io::Result<DocFields> {
+        let required = |name: &str| {
+            schema.get_field(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("index schema has no field {}", name),
+                )
+            })
+        };
+        Ok(DocFields {
+            uri: required("uri")?,
+            title: required("title")?,
+            body: required("body")?,
+            date: required("date")?,
+            language: schema.get_field("language"),
+            record_id: schema.get_field("record_id"),
+            host: schema.get_field("host"),
+            scheme: schema.get_field("scheme"),
+            domain: schema.get_field("domain"),
+            tld: schema.get_field("tld"),
+            site: schema.get_field("site"),
+            content_length: schema.get_field("content_length"),
+            content_hash: schema.get_field("content_hash"),
+            block_digest: schema.get_field("block_digest"),
+            simhash: schema.get_field("simhash"),
+            duplicate_of: schema.get_field("duplicate_of"),
+            body_zstd: schema.get_field("body_zstd"),
+            body_excerpt: schema.get_field("body_excerpt"),
+            source_file: schema.get_field("source_file"),
+            source_offset: schema.get_field("source_offset"),
+            parent_id: schema.get_field("parent_id"),
+            passage: schema.get_field("passage"),
+            passage_start: schema.get_field("passage_start"),
+            passage_end: schema.get_field("passage_end"),
+            excerpt_chars: DEFAULT_EXCERPT_CHARS,
+            schema: schema.clone(),
+        })
+    }
+
+    // The `title` and `body` fields a document in `language` is indexed into,
+    // the language specific pair if the schema has one, `title` and `body` otherwise.
+    // Macrolanguages such as `zho` use the fields of their individual language.
+    pub fn text_fields(&self, language: Option<&str>) -> (Field, Field) {
+        if let Some(language) = language.map(lang::normalize) {
+            let title = self.schema.get_field(&format!("title_{}", language));
+            let body = self.schema.get_field(&format!("body_{}", language));
+            if let (Some(title), Some(body)) = (title, body) {
+                return (title, body);
+            }
+        }
+        (self.title, self.body)
+    }
+
+    pub fn document(&self, doc: &DocJson) -> Document {
+        let language = doc.language.as_deref().map(lang::normalize);
+        let (title, body) = self.text_fields(language.as_deref());
+        let mut document = Document::default();
+        document.add_text(self.uri, &doc.uri);
+        if let Some(date) = dates::parse(&doc.date) {
+            document.add_date(self.date, &date);
+        }
+        document.add_text(title, &doc.title);
+        document.add_text(body, &doc.body);
+        let optional = [
+            (self.language, &language),
+            (self.record_id, &doc.record_id),
+            (self.host, &doc.host),
+            (self.scheme, &doc.scheme),
+            (self.domain, &doc.domain),
+            (self.tld, &doc.tld),
+            (self.content_hash, &doc.content_hash),
+            (self.block_digest, &doc.block_digest),
+            (self.source_file, &doc.source_file),
+            (self.parent_id, &doc.parent_id),
+        ];
+        for (field, value) in optional.iter() {
+            if let (Some(field), Some(value)) = (field, value) {
+                document.add_text(*field, value);
+            }
+        }
+        if let (Some(field), Some(tld), Some(domain), Some(host)) =
+            (self.site, &doc.tld, &doc.domain, &doc.host)
+        {
+            document.add_facet(field, Facet::from_path(vec![tld, domain, host]));
+        }
+        let numbers = [
+            (self.content_length, doc.content_length),
+            (self.simhash, doc.simhash),
+            (self.duplicate_of, doc.duplicate_of),
+            (self.source_offset, doc.source_offset),
+            (self.passage, doc.passage),
+            (self.passage_start, doc.passage_start),
+            (self.passage_end, doc.passage_end),
+        ];
+        for (field, value) in numbers.iter() {
+            if let (Some(field), Some(value)) = (field, value) {
+                document.add_u64(*field, *value);
+            }
+        }
+        if let Some(field) = self.body_excerpt {
+            let end = doc
+                .body
+                .char_indices()
+                .nth(self.excerpt_chars)
+                .map_or(doc.body.len(), |(i, _)| i);
+            document.add_text(field, &doc.body[..end]);
+        }
+        if let Some(field) = self.body_zstd {
+            if let Ok(compressed) = zstd::bulk::compress(doc.body.as_bytes(), 0) {
+                document.add_bytes(field, compressed);
+            }
+        }
+        document
+    }
+}
@@ -0,0 +1,414 @@
+use std;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, NamedFieldDocument, Value};
+use tantivy::{DocAddress, Document, Index, IndexReader, Snippet, SnippetGenerator, Term};
+
+use crate::chunk;
+use crate::dates;
+use crate::tokenizers;
+use crate::warc;
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+fn missing_field(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("index has no field {} to filter by", name),
+    )
+}
+
+// Characters of body text around the matches shown per hit.
+const SNIPPET_CHARS: usize = 200;
+
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub score: f32,
+    // `<segment>:<doc>`, only valid for the searched version of the index
+    pub address: String,
+    pub uri: String,
+    pub title: String,
+    pub date: String,
+    // the matches in the body as HTML, with `<b>` around the query terms
+    pub snippet: String,
+    #[serde(skip)]
+    pub highlighted: String,
+    // all stored fields, for callers selecting other fields than the above
+    #[serde(skip)]
+    pub doc: NamedFieldDocument,
+}
+
+// The snippet of a hit, or the record to read its body back from for one,
+// see `IndexSearcher::snippet`.
+enum SnippetSource {
+    Done(Option<Snippet>),
+    ReadBack {
+        // index into the snippet generators
+        generator: usize,
+        file: String,
+        offset: u64,
+        // the snippet of the kept body, for when reading back fails
+        kept: Option<Snippet>,
+    },
+}
+
+// Restrictions of a search besides the query.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    // dates from `from` (inclusive) to `to` (exclusive)
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // registered domain, e.g. `bbc.co.uk`
+    pub domain: Option<String>,
+}
+
+// The query terms of `snippet` in bold for the terminal.
+fn highlight(snippet: &Snippet) -> String {
+    let text = snippet.fragments();
+    let mut result = String::new();
+    let mut from = 0;
+    for (start, end) in snippet.highlighted().iter().map(|h| h.bounds()) {
+        result.push_str(&text[from..start]);
+        result.push_str("\x1b[1m");
+        result.push_str(&text[start..end]);
+        result.push_str("\x1b[0m");
+        from = end;
+    }
+    result.push_str(&text[from..]);
+    result.replace('\n', " ")
+}
+
+fn text(doc: &Document, field: Option<Field>) -> String {
+    field
+        .and_then(|field| doc.get_first(field))
+        .and_then(|value| value.text())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// An index opened for searching, see `search`.
+pub struct IndexSearcher {
+    pub index: Index,
+    reader: IndexReader,
+    // `title`, `body` and their per-language variants
+    query_fields: Vec<Field>,
+    body_fields: Vec<Field>,
+    uri: Option<Field>,
+    // `title` and, in a multilingual index, the `title_<lang>` fields
+    title_fields: Vec<Field>,
+    date: Option<Field>,
+    domain: Option<Field>,
+    body: Option<Field>,
+    language: Option<Field>,
+    // what is kept of bodies that are not stored, see `schema::BodyStore`
+    body_zstd: Option<Field>,
+    body_excerpt: Option<Field>,
+    source_file: Option<Field>,
+    source_offset: Option<Field>,
+    // where passages are in the body read back, see `chunk`
+    passage_start: Option<Field>,
+    passage_end: Option<Field>,
+}
+
+impl IndexSearcher {
+    pub fn open(dir: &Path) -> io::Result<IndexSearcher> {
+        let index = Index::open_in_dir(dir).map_err(to_io_error)?;
+        tokenizers::register(&index);
+        let schema = index.schema();
+        let mut query_fields = Vec::new();
+        let mut body_fields = Vec::new();
+        let mut title_fields = Vec::new();
+        for (field, entry) in schema.fields() {
+            let name = entry.name();
+            // `body_excerpt` is only stored
+            let is_text = matches!(entry.field_type(), FieldType::Str(_)) && entry.is_indexed();
+            if is_text && (name == "title" || name.starts_with("title_")) {
+                query_fields.push(field);
+                title_fields.push(field);
+            }
+            if is_text && (name == "body" || name.starts_with("body_")) {
+                query_fields.push(field);
+                body_fields.push(field);
+            }
+        }
+        let reader = index.reader().map_err(to_io_error)?;
+        Ok(IndexSearcher {
+            reader,
+            query_fields,
+            body_fields,
+            uri: schema.get_field("uri"),
+            title_fields,
+            date: schema.get_field("date"),
+            domain: schema.get_field("domain"),
+            body: schema.get_field("body"),
+            language: schema.get_field("language"),
+            body_zstd: schema.get_field("body_zstd"),
+            body_excerpt: schema.get_field("body_excerpt"),
+            source_file: schema.get_field("source_file"),
+            source_offset: schema.get_field("source_offset"),
+            passage_start: schema.get_field("passage_start"),
+            passage_end: schema.get_field("passage_end"),
+            index,
+        })
+    }
+
+    pub fn parse(&self, query: &str) -> io::Result<Box<dyn Query>> {
+        let parser = QueryParser::for_index(&self.index, self.query_fields.clone());
+        parser.parse_query(query).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid query {}: {:?}", query, e),
+            )
+        })
+    }
+
+    pub fn searcher(&self) -> tantivy::Searcher {
+        self.reader.searcher()
+    }
+
+    // The body kept besides the postings of an index not storing bodies in
+    // full, decompressed or truncated.
+    fn kept_body(&self, doc: &Document) -> Option<String> {
+        if let Some(Value::Bytes(compressed)) = self.body_zstd.and_then(|f| doc.get_first(f)) {
+            let body = zstd::decode_all(&compressed[..]).ok()?;
+            return Some(String::from_utf8_lossy(&body).into_owned());
+        }
+        self.body_excerpt
+            .and_then(|field| doc.get_first(field))
+            .and_then(|value| value.text())
+            .map(|text| text.to_string())
+    }
+
+    // The part of the record's `body` that `doc` holds, all of it unless
+    // `doc` is a passage.
+    fn passage_of<'a>(&self, doc: &Document, body: &'a str) -> &'a str {
+        let number = |field: Option<Field>| match field.and_then(|f| doc.get_first(f)) {
+            Some(Value::U64(number)) => Some(*number),
+            _ => None,
+        };
+        match (number(self.passage_start), number(self.passage_end)) {
+            (Some(start), Some(end)) => chunk::passage_text(body, start, end),
+            _ => body,
+        }
+    }
+
+    // The snippet of the stored body, else of the kept body or, if that has
+    // no matches, where to read the body back from for one.
+    fn snippet(&self, doc: &Document, generators: &[(Field, SnippetGenerator)]) -> SnippetSource {
+        // the body is in `body` or the field of the document's language
+        if let Some((_, generator)) = generators
+            .iter()
+            .find(|(field, _)| doc.get_first(*field).is_some())
+        {
+            return SnippetSource::Done(Some(generator.snippet_from_doc(doc)));
+        }
+        let language = text(doc, self.language);
+        let field = match self
+            .index
+            .schema()
+            .get_field(&format!("body_{}", language))
+            .filter(|field| self.body_fields.contains(field))
+            .or(self.body)
+        {
+            Some(field) => field,
+            None => return SnippetSource::Done(None),
+        };
+        let generator = match generators.iter().position(|(f, _)| *f == field) {
+            Some(generator) => generator,
+            None => return SnippetSource::Done(None),
+        };
+        let kept = self
+            .kept_body(doc)
+            .map(|body| generators[generator].1.snippet(&body));
+        if let Some(snippet) = &kept {
+            if !snippet.highlighted().is_empty() {
+                return SnippetSource::Done(kept);
+            }
+        }
+        let file = text(doc, self.source_file);
+        match self.source_offset.and_then(|field| doc.get_first(field)) {
+            Some(Value::U64(offset)) if !file.is_empty() => SnippetSource::ReadBack {
+                generator,
+                file,
+                offset: *offset,
+                kept,
+            },
+            _ => SnippetSource::Done(kept),
+        }
+    }
+
+    // The snippets of `docs`. Bodies to read back are read file by file, each
+    // file once for all of its records.
+    fn snippets(
+        &self,
+        docs: &[Document],
+        generators: &[(Field, SnippetGenerator)],
+    ) -> Vec<Option<Snippet>> {
+        let sources: Vec<SnippetSource> = docs
+            .iter()
+            .map(|doc| self.snippet(doc, generators))
+            .collect();
+        let mut offsets: HashMap<&str, Vec<u64>> = HashMap::new();
+        for source in &sources {
+            if let SnippetSource::ReadBack { file, offset, .. } = source {
+                offsets.entry(file.as_str()).or_default().push(*offset);
+            }
+        }
+        let mut bodies: HashMap<String, HashMap<u64, String>> = HashMap::new();
+        for (file, offsets) in offsets {
+            match warc::read_bodies_at(Path::new(file), &offsets) {
+                Ok(read) => {
+                    bodies.insert(file.to_string(), read);
+                }
+                Err(e) => eprintln!("Cannot read back {}: {}", file, e),
+            }
+        }
+        docs.iter()
+            .zip(sources)
+            .map(|(doc, source)| match source {
+                SnippetSource::Done(snippet) => snippet,
+                SnippetSource::ReadBack {
+                    generator,
+                    file,
+                    offset,
+                    kept,
+                } => match bodies.get(&file).map(|read| read.get(&offset)) {
+                    Some(Some(body)) => {
+                        Some(generators[generator].1.snippet(self.passage_of(doc, body)))
+                    }
+                    Some(None) => {
+                        eprintln!("Cannot read back {}:{}: no record", file, offset);
+                        kept
+                    }
+                    None => kept,
+                },
+            })
+            .collect()
+    }
+
+    // `query` restricted to the documents passing `filters`.
+    pub fn filtered(&self, query: &str, filters: &Filters) -> io::Result<Box<dyn Query>> {
+        let query = self.parse(query)?;
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if filters.from.is_some() || filters.to.is_some() {
+            let field = self.date.ok_or_else(|| missing_field("date"))?;
+            let from = filters.from.unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let to = filters.to.unwrap_or(DateTime::<Utc>::MAX_UTC);
+            clauses.push((Occur::Must, Box::new(RangeQuery::new_date(field, from..to))));
+        }
+        if let Some(domain) = &filters.domain {
+            let field = self.domain.ok_or_else(|| missing_field("domain"))?;
+            let term = Term::from_field_text(field, &domain.to_lowercase());
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+            ));
+        }
+        if clauses.is_empty() {
+            return Ok(query);
+        }
+        clauses.push((Occur::Must, query));
+        Ok(Box::new(BooleanQuery::from(clauses)))
+    }
+
+    // The number of matching documents and the best `limit` of them.
+    pub fn search(&self, query: &str, limit: usize) -> io::Result<(usize, Vec<Hit>)> {
+        self.search_page(query, &Filters::default(), 0, limit, true)
+    }
+
+    // The number of documents matching `query` and `filters` and the hits
+    // ranked `offset` to `offset + limit`. Without `snippets` the hits have
+    // none, which saves reading back bodies that are not stored.
+    pub fn search_page(
+        &self,
+        query: &str,
+        filters: &Filters,
+        offset: usize,
+        limit: usize,
+        snippets: bool,
+    ) -> io::Result<(usize, Vec<Hit>)> {
+        let query = self.filtered(query, filters)?;
+        let searcher = self.reader.searcher();
+        let top = TopDocs::with_limit(limit).and_offset(offset);
+        let (count, top_docs) = searcher
+            .search(&*query, &(Count, top))
+            .map_err(to_io_error)?;
+
+        let mut generators = Vec::new();
+        for field in self.body_fields.iter().filter(|_| snippets) {
+            let mut generator =
+                SnippetGenerator::create(&searcher, &*query, *field).map_err(to_io_error)?;
+            generator.set_max_num_chars(SNIPPET_CHARS);
+            generators.push((*field, generator));
+        }
+
+        let mut docs = Vec::new();
+        for (_, address) in &top_docs {
+            docs.push(searcher.doc(*address).map_err(to_io_error)?);
+        }
+        let snippets = if snippets {
+            self.snippets(&docs, &generators)
+        } else {
+            docs.iter().map(|_| None).collect()
+        };
+
+        let mut hits = Vec::new();
+        for (((score, address), doc), snippet) in top_docs.into_iter().zip(docs).zip(snippets) {
+            let DocAddress {
+                segment_ord,
+                doc_id,
+            } = address;
+            let date = match self.date.and_then(|field| doc.get_first(field)) {
+                Some(Value::Date(date)) => dates::format(*date),
+                _ => String::new(),
+            };
+            hits.push(Hit {
+                score,
+                address: format!("{}:{}", segment_ord, doc_id),
+                uri: text(&doc, self.uri),
+                // a document's title is in one of them only
+                title: self
+                    .title_fields
+                    .iter()
+                    .map(|field| text(&doc, Some(*field)))
+                    .find(|title| !title.is_empty())
+                    .unwrap_or_default(),
+                date,
+                snippet: snippet.as_ref().map(|s| s.to_html()).unwrap_or_default(),
+                highlighted: snippet.as_ref().map(highlight).unwrap_or_default(),
+                doc: self.index.schema().to_named_doc(&doc),
+            });
+        }
+        Ok((count, hits))
+    }
+}
+
+// `search` subcommand: prints the hits for `query` in the index in `dir`,
+// one block per hit or, with `json`, a single JSON object.
+pub fn run(dir: &Path, query: &str, limit: usize, json: bool) -> io::Result<()> {
+    let searcher = IndexSearcher::open(dir)?;
+    let (count, hits) = searcher.search(query, limit)?;
+    if json {
+        let result = serde_json::json!({ "query": query, "count": count, "hits": hits });
+        println!("{}", result);
+        return Ok(());
+    }
+    println!("{} documents match {}", count, query);
+    for (rank, hit) in hits.iter().enumerate() {
+        println!();
+        println!("{}. {} ({:.3})", rank + 1, hit.uri, hit.score);
+        println!("   {} {}", hit.date, hit.title.replace('\n', " "));
+        if !hit.highlighted.is_empty() {
+            println!("   {}", hit.highlighted);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::ops::Range;
+
+// Words before a `.` that does not end a sentence, lower cased. Single
+// upper case letters, e.g. initials, are abbreviations as well.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "vs", "e.g", "i.e", "cf", "no", "fig", "jr", "sr",
+    "inc", "ltd", "co", "approx",
+];
+
+// `text[range]` without surrounding whitespace, `None` if nothing is left.
+fn trimmed(text: &str, range: Range<usize>) -> Option<Range<usize>> {
+    let part = &text[range.clone()];
+    let start = range.start + (part.len() - part.trim_start().len());
+    let end = range.start + part.trim_end().len();
+    if start < end {
+        Some(start..end)
+    } else {
+        None
+    }
+}
+
+fn is_abbreviation(word: &str) -> bool {
+    let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let mut chars = word.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_uppercase() => true,
+        _ => ABBREVIATIONS.contains(&word.to_lowercase().as_str()),
+    }
+}
+
+// Byte ranges of the paragraphs of `text`, its non-empty lines without
+// surrounding whitespace. WET bodies have every block of a page on a line
+// of its own.
+pub fn paragraphs(text: &str) -> Vec<Range<usize>> {
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        let end = start + line.len();
+        paragraphs.extend(trimmed(text, start..end));
+        start = end + 1;
+    }
+    paragraphs
+}
+
+// Appends the sentences of the paragraph `text[paragraph]` to `sentences`.
+// A sentence ends with `.`, `!`, `?` or `…`, maybe followed by more of them
+// and closing quotes or brackets, before whitespace or the paragraph's end,
+// but not with the `.` of an abbreviation. `。`, `！` and `？` always end one.
+fn paragraph_sentences(text: &str, paragraph: Range<usize>, sentences: &mut Vec<Range<usize>>) {
+    let chars: Vec<(usize, char)> = text[paragraph.clone()]
+        .char_indices()
+        .map(|(i, c)| (paragraph.start + i, c))
+        .collect();
+    let offset_of = |i: usize| chars.get(i).map_or(paragraph.end, |(offset, _)| *offset);
+    let mut start = paragraph.start;
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        let end = match c {
+            '。' | '！' | '？' => Some(i + 1),
+            '.' | '!' | '?' | '…' => {
+                let mut j = i + 1;
+                while j < chars.len()
+                    && matches!(
+                        chars[j].1,
+                        '.' | '!' | '?' | '…' | '"' | '\'' | '”' | '’' | ')' | ']'
+                    )
+                {
+                    j += 1;
+                }
+                let before_space = j == chars.len() || chars[j].1.is_whitespace();
+                let word = text[start..offset].rsplit(char::is_whitespace).next();
+                let abbreviation = c == '.' && j == i + 1 && word.map_or(false, is_abbreviation);
+                Some(j).filter(|_| before_space && !abbreviation)
+            }
+            _ => None,
+        };
+        match end {
+            Some(j) => {
+                sentences.extend(trimmed(text, start..offset_of(j)));
+                start = offset_of(j);
+                i = j;
+            }
+            None => i += 1,
+        }
+    }
+    sentences.extend(trimmed(text, start..paragraph.end));
+}
+
+// Byte ranges of the sentences of `text`, none spanning paragraphs.
+pub fn sentences(text: &str) -> Vec<Range<usize>> {
+    let mut sentences = Vec::new();
+    for paragraph in paragraphs(text) {
+        paragraph_sentences(text, paragraph, &mut sentences);
+    }
+    sentences
+}
+
+// Byte ranges of the words of `text`, its runs of non-whitespace.
+pub fn words(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..text.len());
+    }
+    words
+}
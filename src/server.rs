@@ -0,0 +1,458 @@
+use std;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Map, Value};
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::{IndexRecordOption, NamedFieldDocument};
+use tantivy::Term;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::dates;
+use crate::search::{Filters, IndexSearcher};
+use crate::stats::{IndexStats, StatsOptions};
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// Fields of a hit returned without `fields`.
+const DEFAULT_FIELDS: [&str; 3] = ["uri", "title", "date"];
+const DEFAULT_LIMIT: usize = 10;
+// Every hit may read back a WARC record for its snippet, see `search`.
+const MAX_LIMIT: usize = 100;
+// Collecting a page keeps `offset + limit` hits in memory.
+const MAX_HITS: usize = 10_000;
+// Requests are a request line and headers only, larger ones are refused.
+const MAX_REQUEST_BYTES: usize = 16 * 1024;
+
+// A JSON response with its HTTP status.
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+// The first value of every `fields` of `doc` that has one, all fields if
+// `fields` is empty.
+fn select(doc: &NamedFieldDocument, fields: &[String]) -> Map<String, Value> {
+    let mut selected = Map::new();
+    for (name, values) in doc.0.iter() {
+        if !fields.is_empty() && !fields.contains(name) {
+            continue;
+        }
+        if let Some(value) = values.first() {
+            let value = serde_json::to_value(value).unwrap_or(Value::Null);
+            selected.insert(name.clone(), value);
+        }
+    }
+    selected
+}
+
+fn fields_param(params: &HashMap<String, String>, default: &[&str]) -> Vec<String> {
+    match params.get("fields") {
+        Some(fields) => fields
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect(),
+        None => default.iter().map(|field| field.to_string()).collect(),
+    }
+}
+
+fn number_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: usize,
+) -> Result<usize, Response> {
+    match params.get(name) {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| Response::error(400, &format!("{} is not a number: {}", name, value))),
+        None => Ok(default),
+    }
+}
+
+// Dates are RFC 3339 or reduced to the day, e.g. `2020-01-01`.
+fn date_param(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, Response> {
+    match params.get(name) {
+        Some(value) => dates::from_warc_date(value)
+            .and_then(|date| dates::parse(&date))
+            .map(Some)
+            .ok_or_else(|| Response::error(400, &format!("{} is not a date: {}", name, value))),
+        None => Ok(None),
+    }
+}
+
+// `/search?q=<query>`, optionally with `offset`, `limit`, `fields`, `from`,
+// `to`, `domain` and `snippets=false`.
+fn search(
+    searcher: &IndexSearcher,
+    params: &HashMap<String, String>,
+) -> Result<Response, Response> {
+    let query = params
+        .get("q")
+        .ok_or_else(|| Response::error(400, "missing parameter q"))?;
+    let offset = number_param(params, "offset", 0)?;
+    let limit = number_param(params, "limit", DEFAULT_LIMIT)?.min(MAX_LIMIT);
+    if offset > MAX_HITS - limit {
+        let message = format!("offset plus limit is more than {}", MAX_HITS);
+        return Err(Response::error(400, &message));
+    }
+    let fields = fields_param(params, &DEFAULT_FIELDS);
+    let snippets = params.get("snippets").map(|s| s.as_str()) != Some("false");
+    let filters = Filters {
+        from: date_param(params, "from")?,
+        to: date_param(params, "to")?,
+        domain: params.get("domain").cloned(),
+    };
+    let (count, hits) = searcher
+        .search_page(query, &filters, offset, limit, snippets)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidInput => Response::error(400, &e.to_string()),
+            _ => Response::error(500, &e.to_string()),
+        })?;
+    let hits: Vec<Value> = hits
+        .iter()
+        .map(|hit| {
+            let mut result = select(&hit.doc, &fields);
+            // titles of a multilingual index are in `title_<lang>` fields
+            if fields.is_empty() || fields.iter().any(|field| field == "title") {
+                result.insert("title".to_string(), json!(hit.title));
+            }
+            result.insert("score".to_string(), json!(hit.score));
+            result.insert("address".to_string(), json!(hit.address));
+            if snippets {
+                result.insert("snippet".to_string(), json!(hit.snippet));
+            }
+            Value::Object(result)
+        })
+        .collect();
+    Ok(Response::ok(json!({
+        "query": query,
+        "count": count,
+        "offset": offset,
+        "limit": limit,
+        "hits": hits,
+    })))
+}
+
+// `/doc/<record_id>`, the stored fields of the document with this record id,
+// optionally only `fields`.
+fn doc(
+    searcher: &IndexSearcher,
+    id: &str,
+    params: &HashMap<String, String>,
+) -> Result<Response, Response> {
+    let schema = searcher.index.schema();
+    let field = schema
+        .get_field("record_id")
+        .ok_or_else(|| Response::error(404, "index has no record ids"))?;
+    let query = TermQuery::new(Term::from_field_text(field, id), IndexRecordOption::Basic);
+    let index_searcher = searcher.searcher();
+    let top_docs = index_searcher
+        .search(&query, &TopDocs::with_limit(1))
+        .map_err(|e| Response::error(500, &format!("{:?}", e)))?;
+    let (_, address) = top_docs
+        .first()
+        .ok_or_else(|| Response::error(404, &format!("no document {}", id)))?;
+    let doc = index_searcher
+        .doc(*address)
+        .map_err(|e| Response::error(500, &format!("{:?}", e)))?;
+    let fields = fields_param(params, &[]);
+    Ok(Response::ok(Value::Object(select(
+        &schema.to_named_doc(&doc),
+        &fields,
+    ))))
+}
+
+// `/stats`, the same as the `stats` subcommand with its defaults.
+fn stats(searcher: &IndexSearcher) -> Response {
+    let index_searcher = searcher.searcher();
+    match IndexStats::collect(&searcher.index, &index_searcher, &StatsOptions::default()) {
+        Ok(stats) => Response::ok(serde_json::to_value(stats).unwrap_or(Value::Null)),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+fn route(searcher: &IndexSearcher, method: &str, target: &str) -> Response {
+    if method != "GET" {
+        return Response::error(405, "only GET is supported");
+    }
+    let url = match url::Url::parse(&format!("http://localhost{}", target)) {
+        Ok(url) => url,
+        Err(_) => return Response::error(400, "invalid request target"),
+    };
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let path = url.path();
+    let result = if path == "/search" {
+        search(searcher, &params)
+    } else if path == "/stats" {
+        Ok(stats(searcher))
+    } else if let Some(id) = path.strip_prefix("/doc/") {
+        // record ids like `<urn:uuid:...>` arrive percent-encoded
+        let id = percent_decode_str(id).decode_utf8_lossy();
+        doc(searcher, &id, &params)
+    } else {
+        Err(Response::error(404, &format!("no endpoint {}", path)))
+    };
+    result.unwrap_or_else(|response| response)
+}
+
+// Reads the request line and headers, bodies are not supported.
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<(String, String)>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..n]);
+        if request.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => Ok(Some((method.to_string(), target.to_string()))),
+        _ => Ok(None),
+    }
+}
+
+async fn handle(searcher: Arc<IndexSearcher>, mut stream: TcpStream) -> io::Result<()> {
+    let response = match read_request(&mut stream).await? {
+        Some((method, target)) => {
+            tokio::task::spawn_blocking(move || route(&searcher, &method, &target))
+                .await
+                .unwrap_or_else(|e| Response::error(500, &format!("{:?}", e)))
+        }
+        None => Response::error(400, "invalid request"),
+    };
+    let body = response.body.to_string();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// `serve` subcommand: answers JSON requests to `/search`, `/doc/<record_id>`
+// and `/stats` for the index in `dir` on `addr`. The index is reloaded when
+// it is committed to, so a running indexer's documents show up.
+pub async fn run(dir: &Path, addr: &str) -> io::Result<()> {
+    let searcher = Arc::new(IndexSearcher::open(dir)?);
+    let listener = TcpListener::bind(addr).await?;
+    println!(
+        "Serving {} on http://{}",
+        dir.to_string_lossy(),
+        listener.local_addr().map_err(to_io_error)?
+    );
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let searcher = searcher.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(searcher, stream).await {
+                eprintln!("Error answering {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{self, DocFields, SchemaOptions, SourceType};
+    use crate::warc::DocJson;
+
+    // An index of five pages, two per domain and month.
+    fn searcher(dir: &Path) -> IndexSearcher {
+        let expected = schema::build_schema(SourceType::Warc, SchemaOptions::default());
+        let index = schema::open_or_create_index(dir, &expected).unwrap();
+        crate::tokenizers::register(&index);
+        let fields = DocFields::resolve(&index.schema()).unwrap();
+        let mut writer = index.writer(15_000_000).unwrap();
+        for i in 0..5 {
+            let domain = if i % 2 == 0 {
+                "example.com"
+            } else {
+                "example.org"
+            };
+            writer.add_document(fields.document(&DocJson {
+                uri: format!("http://www.{}/{}", domain, i),
+                title: format!("Page {}", i),
+                body: format!("a budget page, number {}", i),
+                date: format!("2020-0{}-15T00:00:00Z", 1 + i / 2),
+                record_id: Some(format!("<urn:uuid:{}>", i)),
+                host: Some(format!("www.{}", domain)),
+                domain: Some(domain.to_string()),
+                tld: domain.rsplit('.').next().map(|tld| tld.to_string()),
+                ..DocJson::default()
+            }));
+        }
+        writer.commit().unwrap();
+        IndexSearcher::open(dir).unwrap()
+    }
+
+    fn get(searcher: &IndexSearcher, target: &str) -> Response {
+        route(searcher, "GET", target)
+    }
+
+    fn uris(response: &Response) -> Vec<&str> {
+        response.body["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["uri"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn search_returns_default_fields_and_snippets() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = searcher(dir.path());
+        let response = get(&searcher, "/search?q=budget");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["count"], 5);
+        assert_eq!(response.body["limit"], DEFAULT_LIMIT);
+        let hit = response.body["hits"][0].as_object().unwrap();
+        let mut keys: Vec<&str> = hit.keys().map(|key| key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            ["address", "date", "score", "snippet", "title", "uri"]
+        );
+        assert!(hit["snippet"].as_str().unwrap().contains("<b>budget</b>"));
+
+        let response = get(&searcher, "/search?q=budget&fields=uri&snippets=false");
+        let hit = response.body["hits"][0].as_object().unwrap();
+        let mut keys: Vec<&str> = hit.keys().map(|key| key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["address", "score", "uri"]);
+    }
+
+    #[test]
+    fn search_pages_through_hits() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = searcher(dir.path());
+        let all = uris(&get(&searcher, "/search?q=budget")).join(" ");
+        let mut pages = Vec::new();
+        for offset in [0, 2, 4].iter() {
+            let target = format!("/search?q=budget&offset={}&limit=2", offset);
+            let response = get(&searcher, &target);
+            assert_eq!(response.body["count"], 5);
+            pages.extend(uris(&response).into_iter().map(|uri| uri.to_string()));
+        }
+        assert_eq!(pages.join(" "), all);
+
+        let response = get(&searcher, "/search?q=budget&limit=100000");
+        assert_eq!(response.body["limit"], MAX_LIMIT);
+        assert_eq!(get(&searcher, "/search?q=budget&offset=x").status, 400);
+        let target = format!("/search?q=budget&offset={}&limit=10", MAX_HITS - 10);
+        assert_eq!(get(&searcher, &target).status, 200);
+        let target = format!("/search?q=budget&offset={}&limit=10", MAX_HITS - 9);
+        assert_eq!(get(&searcher, &target).status, 400);
+        let target = "/search?q=budget&offset=18446744073709551000";
+        assert_eq!(get(&searcher, target).status, 400);
+        assert_eq!(get(&searcher, "/search").status, 400);
+    }
+
+    #[test]
+    fn search_filters_by_date_and_domain() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = searcher(dir.path());
+        // pages 2 and 3 are from February
+        let response = get(&searcher, "/search?q=budget&from=2020-02-01&to=2020-03-01");
+        let mut found = uris(&response);
+        found.sort_unstable();
+        assert_eq!(
+            found,
+            ["http://www.example.com/2", "http://www.example.org/3"]
+        );
+
+        let response = get(&searcher, "/search?q=budget&domain=Example.ORG");
+        let mut found = uris(&response);
+        found.sort_unstable();
+        assert_eq!(
+            found,
+            ["http://www.example.org/1", "http://www.example.org/3"]
+        );
+
+        let response = get(
+            &searcher,
+            "/search?q=budget&from=2020-03-01&domain=example.com",
+        );
+        assert_eq!(uris(&response), ["http://www.example.com/4"]);
+        assert_eq!(get(&searcher, "/search?q=budget&from=March").status, 400);
+    }
+
+    #[test]
+    fn doc_by_record_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = searcher(dir.path());
+        let response = get(&searcher, "/doc/%3Curn%3Auuid%3A3%3E");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["uri"], "http://www.example.org/3");
+        assert_eq!(response.body["body"], "a budget page, number 3");
+
+        let response = get(&searcher, "/doc/%3Curn%3Auuid%3A3%3E?fields=title,domain");
+        assert_eq!(
+            response.body,
+            json!({ "title": "Page 3", "domain": "example.org" })
+        );
+        assert_eq!(get(&searcher, "/doc/%3Curn%3Auuid%3A9%3E").status, 404);
+    }
+
+    #[test]
+    fn stats_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let searcher = searcher(dir.path());
+        let response = get(&searcher, "/stats");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["docs"], 5);
+        assert_eq!(response.body["domains"][0]["value"], "example.com");
+        assert_eq!(response.body["domains"][0]["docs"], 3);
+
+        assert_eq!(get(&searcher, "/unknown").status, 404);
+        assert_eq!(route(&searcher, "POST", "/stats").status, 405);
+    }
+}
@@ -0,0 +1,364 @@
+use std;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use flate2::write::GzEncoder;
+use serde::Serialize;
+
+use crate::columnar::ParquetSink;
+use crate::index_sink::{IndexSink, IndexTarget};
+use crate::ndjson::NdjsonEncoder;
+use crate::warc::DocJson;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    pub fn parse(name: &str) -> Option<OutputCompression> {
+        match name {
+            "" | "none" => Some(OutputCompression::None),
+            "gzip" | "gz" => Some(OutputCompression::Gzip),
+            "zstd" | "zst" => Some(OutputCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            OutputCompression::None => None,
+            OutputCompression::Gzip => Some("gz"),
+            OutputCompression::Zstd => Some("zst"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Ndjson,
+    Parquet,
+    // add to the tantivy index in <index>
+    Index,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<OutputFormat> {
+        match name {
+            "" | "json" | "ndjson" => Some(OutputFormat::Ndjson),
+            "parquet" => Some(OutputFormat::Parquet),
+            "index" | "tantivy" => Some(OutputFormat::Index),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub compression: OutputCompression,
+    // roll over to a new part once a part holds this many documents ...
+    pub max_docs: Option<usize>,
+    // ... or this many uncompressed bytes
+    pub max_bytes: Option<u64>,
+    // rows per parquet row group
+    pub row_group_size: usize,
+    // documents per `DocSink::write_batch`, the source's default if unset
+    pub batch_size: Option<usize>,
+    // opened once per run for `OutputFormat::Index`
+    pub index: Option<Arc<IndexTarget>>,
+    // where `OutputFormat::Index` writes embeddings, `<index>.embeddings` if unset
+    pub embeddings_dir: Option<PathBuf>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: OutputFormat::Ndjson,
+            compression: OutputCompression::None,
+            max_docs: None,
+            max_bytes: None,
+            row_group_size: 64 * 1024,
+            batch_size: None,
+            index: None,
+            embeddings_dir: None,
+        }
+    }
+}
+
+// One line for the log of a run, e.g. `ndjson, gzip, 1000 documents per part`.
+impl fmt::Display for OutputOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = match self.format {
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Index => "index",
+        };
+        f.write_str(format)?;
+        match self.compression {
+            OutputCompression::None => (),
+            OutputCompression::Gzip => f.write_str(", gzip")?,
+            OutputCompression::Zstd => f.write_str(", zstd")?,
+        }
+        if let Some(docs) = self.max_docs {
+            write!(f, ", {} documents per part", docs)?;
+        }
+        if let Some(bytes) = self.max_bytes {
+            write!(f, ", {} bytes per part", bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputOptions {
+    fn rolling(&self) -> bool {
+        self.max_docs.is_some() || self.max_bytes.is_some()
+    }
+
+    // The embeddings file of the index output `path`, outside of the index
+    // directory, which is tantivy's.
+    fn embeddings_path(&self, path: &Path) -> io::Result<PathBuf> {
+        let dir = match &self.embeddings_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let index = path
+                    .parent()
+                    .filter(|index| !index.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."));
+                // absolute, so `.` has a name to add `.embeddings` to
+                let index = std::fs::canonicalize(index)?;
+                let mut name = index.file_name().unwrap_or_default().to_os_string();
+                name.push(".embeddings");
+                index.with_file_name(name)
+            }
+        };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        Ok(dir.join(format!("{}.embeddings.json", stem)))
+    }
+}
+
+pub trait DocSink {
+    fn write_batch(&mut self, batch: &[DocJson]) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+// Opens the sink for the documents of one input file. `path` is the `.json`
+// output path, other formats replace the extension.
+pub fn create(
+    path: PathBuf,
+    options: &OutputOptions,
+    source: &str,
+) -> io::Result<Box<dyn DocSink + Send>> {
+    match options.format {
+        OutputFormat::Ndjson => Ok(Box::new(NdjsonSink::create(path, options)?)),
+        OutputFormat::Parquet => Ok(Box::new(ParquetSink::create(
+            path.with_extension("parquet"),
+            options,
+            source,
+        )?)),
+        OutputFormat::Index => Ok(Box::new(IndexSink::new(
+            options
+                .index
+                .clone()
+                .expect("index opened for index output"),
+            options.embeddings_path(&path)?,
+        ))),
+    }
+}
+
+enum PartWriter {
+    Plain(BufWriter<std::fs::File>),
+    Gzip(GzEncoder<BufWriter<std::fs::File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<std::fs::File>>),
+}
+
+impl PartWriter {
+    fn create(path: &Path, compression: OutputCompression) -> io::Result<PartWriter> {
+        // truncate, re-runs must not leave the tail of an older, longer file
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let file = BufWriter::new(file);
+        Ok(match compression {
+            OutputCompression::None => PartWriter::Plain(file),
+            OutputCompression::Gzip => {
+                PartWriter::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            OutputCompression::Zstd => PartWriter::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            PartWriter::Plain(mut w) => w.flush(),
+            PartWriter::Gzip(w) => w.finish()?.flush(),
+            PartWriter::Zstd(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            PartWriter::Plain(w) => w.write(buf),
+            PartWriter::Gzip(w) => w.write(buf),
+            PartWriter::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            PartWriter::Plain(w) => w.flush(),
+            PartWriter::Gzip(w) => w.flush(),
+            PartWriter::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Part {
+    path: String,
+    docs: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    compression: OutputCompression,
+    parts: &'a [Part],
+}
+
+// Writes the documents extracted from one input file as NDJSON, optionally
+// compressed and split into numbered parts. When rolling is enabled a
+// `<stem>.manifest.json` next to the parts lists them in order.
+pub struct NdjsonSink {
+    path: PathBuf,
+    options: OutputOptions,
+    current: Option<NdjsonEncoder<PartWriter>>,
+    parts: Vec<Part>,
+}
+
+impl NdjsonSink {
+    pub fn create(path: PathBuf, options: &OutputOptions) -> io::Result<NdjsonSink> {
+        let mut sink = NdjsonSink {
+            path,
+            options: options.clone(),
+            current: None,
+            parts: Vec::new(),
+        };
+        sink.roll()?;
+        Ok(sink)
+    }
+
+    fn part_path(&self, part: usize) -> PathBuf {
+        let mut name = self
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if self.options.rolling() {
+            name.push_str(&format!(".{:05}", part));
+        }
+        if let Some(extension) = self.path.extension() {
+            name.push('.');
+            name.push_str(&extension.to_string_lossy());
+        }
+        if let Some(extension) = self.options.compression.extension() {
+            name.push('.');
+            name.push_str(extension);
+        }
+        self.path.with_file_name(name)
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        if let Some(current) = self.current.take() {
+            current.into_inner().finish()?;
+        }
+        let path = self.part_path(self.parts.len());
+        let writer = PartWriter::create(&path, self.options.compression)?;
+        self.current = Some(NdjsonEncoder::new(writer));
+        self.parts.push(Part {
+            path: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            docs: 0,
+            bytes: 0,
+        });
+        Ok(())
+    }
+
+    // Removes the parts an earlier run rolled into beyond the ones of this
+    // run, so they are not mistaken for output of the same input file.
+    fn remove_stale_parts(&self) -> io::Result<()> {
+        for part in self.parts.len().. {
+            match std::fs::remove_file(self.part_path(part)) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn part_full(&self) -> bool {
+        let part = match self.parts.last() {
+            Some(part) => part,
+            None => return false,
+        };
+        if part.docs == 0 {
+            return false;
+        }
+        self.options.max_docs.map_or(false, |max| part.docs >= max)
+            || self
+                .options
+                .max_bytes
+                .map_or(false, |max| part.bytes >= max)
+    }
+}
+
+impl DocSink for NdjsonSink {
+    fn write_batch(&mut self, batch: &[DocJson]) -> io::Result<()> {
+        for doc in batch {
+            if self.part_full() {
+                self.roll()?;
+            }
+            let bytes = self.current.as_mut().expect("open part").encode(doc)?;
+            let part = self.parts.last_mut().expect("open part");
+            part.docs += 1;
+            part.bytes += bytes;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if let Some(current) = self.current.take() {
+            current.into_inner().finish()?;
+        }
+        if self.options.rolling() {
+            self.remove_stale_parts()?;
+            let manifest = Manifest {
+                compression: self.options.compression,
+                parts: &self.parts,
+            };
+            let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+            let manifest_path = self.path.with_file_name(format!("{}.manifest.json", stem));
+            let json = serde_json::to_string_pretty(&manifest)?;
+            std::fs::write(manifest_path, json)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use std;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use tantivy::collector::FacetCollector;
+use tantivy::postings::TermInfo;
+use tantivy::query::AllQuery;
+use tantivy::schema::{Facet, Field, FieldType, IndexRecordOption};
+use tantivy::{
+    u64_to_i64, DocSet, Index, InvertedIndexReader, Searcher, SegmentReader, TERMINATED,
+};
+
+use crate::tokenizers;
+
+fn to_io_error<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// What `stats` reports besides the counts every index has.
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    // number of domains, TLDs and languages listed
+    pub top: usize,
+    // fields to count terms of, all indexed text fields if empty
+    pub term_fields: Vec<String>,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        StatsOptions {
+            top: 10,
+            term_fields: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SegmentStats {
+    pub id: String,
+    pub docs: u32,
+    pub deleted: u32,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldStats {
+    pub name: String,
+    // bytes of the term dictionary, postings, positions, fast field and
+    // field norms, summed over all segments
+    pub bytes: usize,
+    // distinct terms, summed over segments, and indexed tokens, only for
+    // the term fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Count {
+    pub value: String,
+    pub docs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexStats {
+    pub docs: u64,
+    pub deleted: u64,
+    pub bytes: usize,
+    // bytes of the document store, which is not split by field
+    pub store_bytes: usize,
+    pub segments: Vec<SegmentStats>,
+    pub fields: Vec<FieldStats>,
+    // from the `site` facet
+    pub tlds: Vec<Count>,
+    pub domains: Vec<Count>,
+    // live documents per `language` term
+    pub languages: Vec<Count>,
+    // `YYYY-MM` of the dated documents, in order
+    pub months: Vec<Count>,
+}
+
+fn top(counts: impl Iterator<Item = (String, u64)>, n: usize) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts.map(|(value, docs)| Count { value, docs }).collect();
+    counts.sort_by(|a, b| b.docs.cmp(&a.docs).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(n);
+    counts
+}
+
+// Documents per TLD and per domain, counted on the `/tld/domain/host` facet.
+fn sites(searcher: &Searcher, field: Field, n: usize) -> io::Result<(Vec<Count>, Vec<Count>)> {
+    let mut collector = FacetCollector::for_field(field);
+    collector.add_facet("/");
+    let counts = searcher
+        .search(&AllQuery, &collector)
+        .map_err(to_io_error)?;
+    let tlds: Vec<(Facet, u64)> = counts
+        .get("/")
+        .map(|(facet, docs)| (facet.clone(), docs))
+        .collect();
+
+    let mut collector = FacetCollector::for_field(field);
+    for (tld, _) in &tlds {
+        collector.add_facet(tld.clone());
+    }
+    let counts = searcher
+        .search(&AllQuery, &collector)
+        .map_err(to_io_error)?;
+    let mut domains = Vec::new();
+    for (tld, _) in &tlds {
+        for (facet, docs) in counts.get(tld.clone()) {
+            let domain = facet.to_path().last().map(|s| s.to_string());
+            domains.push((domain.unwrap_or_default(), docs));
+        }
+    }
+    let tlds = tlds.into_iter().map(|(facet, docs)| {
+        let tld = facet.to_path().last().map(|s| s.to_string());
+        (tld.unwrap_or_default(), docs)
+    });
+    Ok((top(tlds, n), top(domains.into_iter(), n)))
+}
+
+// Live documents of the term `info` of a segment: deleted documents stay in
+// the postings and doc freqs until their segment is merged.
+fn live_docs(
+    segment: &SegmentReader,
+    inverted_index: &InvertedIndexReader,
+    info: &TermInfo,
+) -> io::Result<u64> {
+    if !segment.has_deletes() {
+        return Ok(info.doc_freq as u64);
+    }
+    let mut postings =
+        inverted_index.read_postings_from_terminfo(info, IndexRecordOption::Basic)?;
+    let mut docs = 0;
+    let mut doc = postings.doc();
+    while doc != TERMINATED {
+        if !segment.is_deleted(doc) {
+            docs += 1;
+        }
+        doc = postings.advance();
+    }
+    Ok(docs)
+}
+
+// Live documents per term of the raw `field`, from the term dictionaries.
+fn term_counts(searcher: &Searcher, field: Field) -> io::Result<HashMap<String, u64>> {
+    let mut counts = HashMap::new();
+    for segment in searcher.segment_readers() {
+        let inverted_index = segment.inverted_index(field).map_err(to_io_error)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            let docs = live_docs(segment, &inverted_index, stream.value())?;
+            if docs > 0 {
+                let value = String::from_utf8_lossy(stream.key()).into_owned();
+                *counts.entry(value).or_insert(0) += docs;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+// Live documents per month of the `date` field, counted on its terms: a
+// document without a date has none, while its fast field value reads as 0,
+// which would count it in 1970-01.
+fn months(segments: &[SegmentReader], field: Field) -> io::Result<Vec<Count>> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    for segment in segments {
+        let inverted_index = segment.inverted_index(field).map_err(to_io_error)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            let bytes: [u8; 8] = match stream.key().try_into() {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+            let timestamp = u64_to_i64(u64::from_be_bytes(bytes));
+            let date: DateTime<Utc> = match Utc.timestamp_opt(timestamp, 0).single() {
+                Some(date) => date,
+                None => continue,
+            };
+            let docs = live_docs(segment, &inverted_index, stream.value())?;
+            if docs > 0 {
+                *counts.entry(date.format("%Y-%m").to_string()).or_insert(0) += docs;
+            }
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .map(|(value, docs)| Count { value, docs })
+        .collect())
+}
+
+impl IndexStats {
+    pub fn collect(
+        index: &Index,
+        searcher: &Searcher,
+        options: &StatsOptions,
+    ) -> io::Result<IndexStats> {
+        let schema = index.schema();
+        let space = searcher.space_usage().map_err(to_io_error)?;
+
+        let mut segments = Vec::new();
+        let mut field_bytes: HashMap<Field, usize> = HashMap::new();
+        let mut store_bytes = 0;
+        for (reader, usage) in searcher.segment_readers().iter().zip(space.segments()) {
+            segments.push(SegmentStats {
+                id: reader.segment_id().uuid_string(),
+                docs: reader.num_docs(),
+                deleted: reader.num_deleted_docs(),
+                bytes: usage.total(),
+            });
+            let per_field = [
+                usage.termdict(),
+                usage.postings(),
+                usage.positions(),
+                usage.positions_skip_idx(),
+                usage.fast_fields(),
+                usage.fieldnorms(),
+            ];
+            for usage in per_field.iter() {
+                for (field, field_usage) in usage.fields() {
+                    *field_bytes.entry(*field).or_insert(0) += field_usage.total();
+                }
+            }
+            store_bytes += usage.store().total();
+        }
+
+        for name in &options.term_fields {
+            if schema.get_field(name).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("index has no field {}", name),
+                ));
+            }
+        }
+        let term_fields: Vec<Field> = schema
+            .fields()
+            .filter(|(_, entry)| {
+                if options.term_fields.is_empty() {
+                    matches!(entry.field_type(), FieldType::Str(_)) && entry.is_indexed()
+                } else {
+                    options.term_fields.iter().any(|name| name == entry.name())
+                }
+            })
+            .map(|(field, _)| field)
+            .collect();
+        let mut fields = Vec::new();
+        for (field, entry) in schema.fields() {
+            let (mut terms, mut tokens) = (None, None);
+            if term_fields.contains(&field) {
+                let (mut term_count, mut token_count) = (0, 0);
+                for segment in searcher.segment_readers() {
+                    let inverted_index = segment.inverted_index(field).map_err(to_io_error)?;
+                    term_count += inverted_index.terms().num_terms() as u64;
+                    token_count += inverted_index.total_num_tokens();
+                }
+                terms = Some(term_count);
+                tokens = Some(token_count);
+            }
+            fields.push(FieldStats {
+                name: entry.name().to_string(),
+                bytes: field_bytes.get(&field).cloned().unwrap_or(0),
+                terms,
+                tokens,
+            });
+        }
+
+        let (tlds, domains) = match schema.get_field("site") {
+            Some(field) => sites(searcher, field, options.top)?,
+            None => (Vec::new(), Vec::new()),
+        };
+        let languages = match schema.get_field("language") {
+            Some(field) => top(term_counts(searcher, field)?.into_iter(), options.top),
+            None => Vec::new(),
+        };
+        let months = match schema.get_field("date") {
+            Some(field) => months(searcher.segment_readers(), field)?,
+            None => Vec::new(),
+        };
+
+        Ok(IndexStats {
+            docs: searcher.num_docs(),
+            deleted: segments.iter().map(|s| s.deleted as u64).sum(),
+            bytes: space.total(),
+            store_bytes,
+            segments,
+            fields,
+            tlds,
+            domains,
+            languages,
+            months,
+        })
+    }
+}
+
+fn write_counts(f: &mut fmt::Formatter, title: &str, counts: &[Count]) -> fmt::Result {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    writeln!(f)?;
+    writeln!(f, "{}:", title)?;
+    for count in counts {
+        writeln!(f, "  {:<30} {:>12}", count.value, count.docs)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Documents: {}", self.docs)?;
+        writeln!(f, "Deleted documents: {}", self.deleted)?;
+        writeln!(f, "Segments: {}", self.segments.len())?;
+        writeln!(f, "Bytes: {}", self.bytes)?;
+        writeln!(f, "Document store bytes: {}", self.store_bytes)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<30} {:>14} {:>12} {:>14}",
+            "field", "bytes", "terms", "tokens"
+        )?;
+        for field in &self.fields {
+            let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+            writeln!(
+                f,
+                "{:<30} {:>14} {:>12} {:>14}",
+                field.name,
+                field.bytes,
+                optional(field.terms),
+                optional(field.tokens)
+            )?;
+        }
+        write_counts(f, "Top TLDs", &self.tlds)?;
+        write_counts(f, "Top domains", &self.domains)?;
+        write_counts(f, "Top languages", &self.languages)?;
+        write_counts(f, "Documents per month", &self.months)
+    }
+}
+
+// `stats` subcommand: prints the statistics of the index in `dir`, as text
+// or, with `json`, as a single JSON object.
+pub fn run(dir: &Path, options: &StatsOptions, json: bool) -> io::Result<()> {
+    let index = Index::open_in_dir(dir).map_err(to_io_error)?;
+    tokenizers::register(&index);
+    let searcher = index.reader().map_err(to_io_error)?.searcher();
+    let stats = IndexStats::collect(&index, &searcher, options)?;
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        print!("{}", stats);
+    }
+    Ok(())
+}
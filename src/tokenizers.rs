@@ -0,0 +1,322 @@
+use std;
+
+use tantivy::tokenizer::{
+    BoxTokenStream, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, TextAnalyzer,
+    Token, TokenStream, Tokenizer,
+};
+use tantivy::Index;
+
+use crate::segment;
+
+pub const CJK_BIGRAM: &str = "cjk_bigram";
+// Appended to a tokenizer's name for its `SentenceTokenizer` variant.
+pub const SENTENCES_SUFFIX: &str = "_sentences";
+// Positions left empty between sentences, more than the slop phrase queries
+// are usually given.
+const SENTENCE_GAP: usize = 10;
+
+// ISO 639-3 codes as produced by `lang` and the tokenizer their text fields use.
+// Languages not listed here, English included, go to the `en_stem` fields.
+pub const LANGUAGE_TOKENIZERS: &[(&str, &str)] = &[
+    ("ara", "ar_stem"),
+    ("dan", "da_stem"),
+    ("deu", "de_stem"),
+    ("ell", "el_stem"),
+    ("fin", "fi_stem"),
+    ("fra", "fr_stem"),
+    ("hun", "hu_stem"),
+    ("ita", "it_stem"),
+    ("nld", "nl_stem"),
+    ("nob", "no_stem"),
+    ("por", "pt_stem"),
+    ("ron", "ro_stem"),
+    ("rus", "ru_stem"),
+    ("spa", "es_stem"),
+    ("swe", "sv_stem"),
+    ("tam", "ta_stem"),
+    ("tur", "tr_stem"),
+    ("cmn", CJK_BIGRAM),
+    ("jpn", CJK_BIGRAM),
+    ("kor", CJK_BIGRAM),
+];
+
+pub fn tokenizer_for(language: &str) -> Option<&'static str> {
+    LANGUAGE_TOKENIZERS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map(|(_, tokenizer)| *tokenizer)
+}
+
+fn stemmer(name: &str) -> Option<Language> {
+    let language = match name {
+        "ar_stem" => Language::Arabic,
+        "da_stem" => Language::Danish,
+        "de_stem" => Language::German,
+        "el_stem" => Language::Greek,
+        "fi_stem" => Language::Finnish,
+        "fr_stem" => Language::French,
+        "hu_stem" => Language::Hungarian,
+        "it_stem" => Language::Italian,
+        "nl_stem" => Language::Dutch,
+        "no_stem" => Language::Norwegian,
+        "pt_stem" => Language::Portuguese,
+        "ro_stem" => Language::Romanian,
+        "ru_stem" => Language::Russian,
+        "es_stem" => Language::Spanish,
+        "sv_stem" => Language::Swedish,
+        "ta_stem" => Language::Tamil,
+        "tr_stem" => Language::Turkish,
+        _ => return None,
+    };
+    Some(language)
+}
+
+fn stem_analyzer<T: Tokenizer>(tokenizer: T, language: Language) -> TextAnalyzer {
+    TextAnalyzer::from(tokenizer)
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .filter(Stemmer::new(language))
+}
+
+fn cjk_analyzer<T: Tokenizer>(tokenizer: T) -> TextAnalyzer {
+    TextAnalyzer::from(tokenizer)
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+}
+
+// Registers all tokenizers of `LANGUAGE_TOKENIZERS` and their `_sentences`
+// variants, as well as `en_stem_sentences`, with the index. This must be done
+// every time an index is opened, tantivy does not persist them.
+pub fn register(index: &Index) {
+    let manager = index.tokenizers();
+    for (_, name) in LANGUAGE_TOKENIZERS {
+        if let Some(language) = stemmer(name) {
+            manager.register(name, stem_analyzer(SimpleTokenizer, language));
+            manager.register(
+                &sentences(name),
+                stem_analyzer(SentenceTokenizer(SimpleTokenizer), language),
+            );
+        }
+    }
+    manager.register(
+        &sentences("en_stem"),
+        stem_analyzer(SentenceTokenizer(SimpleTokenizer), Language::English),
+    );
+    manager.register(CJK_BIGRAM, cjk_analyzer(CjkBigramTokenizer));
+    manager.register(
+        &sentences(CJK_BIGRAM),
+        cjk_analyzer(SentenceTokenizer(CjkBigramTokenizer)),
+    );
+}
+
+// The name of the `SentenceTokenizer` variant of the tokenizer `name`.
+pub fn sentences(name: &str) -> String {
+    format!("{}{}", name, SENTENCES_SUFFIX)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF     // Hangul Jamo
+        | 0x3040..=0x30FF   // Hiragana, Katakana
+        | 0x3130..=0x318F   // Hangul Compatibility Jamo
+        | 0x31F0..=0x31FF   // Katakana Phonetic Extensions
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF   // Hangul Syllables
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x20000..=0x2A6DF // CJK Extension B
+    )
+}
+
+// Splits runs of CJK characters into overlapping character bigrams, a run of
+// a single character becomes a unigram. Everything else is tokenized like the
+// `SimpleTokenizer` does, so mixed CJK and latin text works as expected.
+#[derive(Clone)]
+pub struct CjkBigramTokenizer;
+
+// Tokens computed up front.
+pub struct TokenVecStream {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl CjkBigramTokenizer {
+    fn tokenize(text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let push = |tokens: &mut Vec<Token>, from: usize, to: usize| {
+            tokens.push(Token {
+                offset_from: from,
+                offset_to: to,
+                position: tokens.len(),
+                text: text[from..to].to_string(),
+                position_length: 1,
+            });
+        };
+
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let end_of = |i: usize| chars.get(i).map_or(text.len(), |(offset, _)| *offset);
+        let mut i = 0;
+        while i < chars.len() {
+            let (start, c) = chars[i];
+            if is_cjk(c) {
+                let mut j = i;
+                while j < chars.len() && is_cjk(chars[j].1) {
+                    j += 1;
+                }
+                if j - i == 1 {
+                    push(&mut tokens, start, end_of(j));
+                } else {
+                    for k in i..j - 1 {
+                        push(&mut tokens, chars[k].0, end_of(k + 2));
+                    }
+                }
+                i = j;
+            } else if c.is_alphanumeric() {
+                let mut j = i;
+                while j < chars.len() && chars[j].1.is_alphanumeric() && !is_cjk(chars[j].1) {
+                    j += 1;
+                }
+                push(&mut tokens, start, end_of(j));
+                i = j;
+            } else {
+                i += 1;
+            }
+        }
+        tokens
+    }
+}
+
+impl Tokenizer for CjkBigramTokenizer {
+    fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(TokenVecStream {
+            tokens: CjkBigramTokenizer::tokenize(text),
+            next: 0,
+        })
+    }
+}
+
+impl TokenStream for TokenVecStream {
+    fn advance(&mut self) -> bool {
+        self.next += 1;
+        self.next <= self.tokens.len()
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.next - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.next - 1]
+    }
+}
+
+// Tokenizes every sentence of a text on its own with the wrapped tokenizer,
+// leaving `SENTENCE_GAP` positions between sentences, so phrase queries do
+// not match across their boundaries. Offsets are those in the whole text.
+#[derive(Clone)]
+pub struct SentenceTokenizer<T>(pub T);
+
+impl<T: Tokenizer + Clone> Tokenizer for SentenceTokenizer<T> {
+    fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
+        let mut tokens = Vec::new();
+        let mut first_position = 0;
+        for sentence in segment::sentences(text) {
+            let mut stream = self.0.token_stream(&text[sentence.clone()]);
+            let mut next_position = first_position;
+            while stream.advance() {
+                let token = stream.token();
+                tokens.push(Token {
+                    offset_from: sentence.start + token.offset_from,
+                    offset_to: sentence.start + token.offset_to,
+                    position: first_position + token.position,
+                    text: token.text.clone(),
+                    position_length: token.position_length,
+                });
+                next_position = first_position + token.position + 1;
+            }
+            if next_position > first_position {
+                first_position = next_position + SENTENCE_GAP;
+            }
+        }
+        BoxTokenStream::from(TokenVecStream { tokens, next: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::FieldType;
+
+    use crate::schema::{self, DocFields, SchemaOptions, SourceType};
+    use crate::warc::DocJson;
+
+    fn tokens(tokenizer: &dyn Tokenizer, text: &str) -> Vec<(String, usize, usize)> {
+        let mut stream = tokenizer.token_stream(text);
+        let mut tokens = Vec::new();
+        while stream.advance() {
+            let token = stream.token();
+            tokens.push((token.text.clone(), token.position, token.offset_from));
+        }
+        tokens
+    }
+
+    #[test]
+    fn cjk_bigrams_of_mixed_text() {
+        let tokens = tokens(&CjkBigramTokenizer, "Tokyo東京都 is big。雨, ok");
+        let expected = [
+            ("Tokyo", 0, 0),
+            // a run of three characters, right after latin ones
+            ("東京", 1, 5),
+            ("京都", 2, 8),
+            ("is", 3, 15),
+            ("big", 4, 18),
+            // a single character between punctuation is a unigram
+            ("雨", 5, 24),
+            ("ok", 6, 29),
+        ];
+        let expected: Vec<(String, usize, usize)> = expected
+            .iter()
+            .map(|(text, position, offset)| (text.to_string(), *position, *offset))
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn macrolanguages_use_the_cjk_fields() {
+        let options = SchemaOptions {
+            multilingual: true,
+            ..SchemaOptions::default()
+        };
+        let schema = schema::build_schema(SourceType::Warc, options);
+        let fields = DocFields::resolve(&schema).unwrap();
+        let cjk = fields.text_fields(Some("cmn"));
+        assert_eq!(fields.text_fields(Some("zho")), cjk);
+        assert_ne!(cjk, (fields.title, fields.body));
+        let indexing = match schema.get_field_entry(cjk.1).field_type() {
+            FieldType::Str(options) => options.get_indexing_options().unwrap(),
+            _ => panic!("{} is no text field", schema.get_field_name(cjk.1)),
+        };
+        assert_eq!(indexing.tokenizer(), CJK_BIGRAM);
+
+        // a document of Common Crawl's `zho` is stored as `cmn`
+        let document = fields.document(&DocJson {
+            body: "東京都".to_string(),
+            language: Some("zho".to_string()),
+            ..DocJson::default()
+        });
+        assert_eq!(
+            document.get_first(cjk.1).and_then(|value| value.text()),
+            Some("東京都")
+        );
+        let language = document.get_first(fields.language.unwrap());
+        assert_eq!(language.and_then(|value| value.text()), Some("cmn"));
+    }
+
+    #[test]
+    fn sentences_leave_a_gap() {
+        let tokens = tokens(&SentenceTokenizer(SimpleTokenizer), "New. York is");
+        let positions: Vec<usize> = tokens.iter().map(|(_, position, _)| *position).collect();
+        assert_eq!(positions, [0, 1 + SENTENCE_GAP, 2 + SENTENCE_GAP]);
+        assert_eq!(tokens[1].2, 5);
+    }
+}
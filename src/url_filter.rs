@@ -0,0 +1,186 @@
+use std;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+use url::Url;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// A `re:` prefixed regex or a glob where `*` matches any run of characters
+// and `?` a single one, matched against the whole URI.
+fn pattern(source: &str) -> io::Result<Regex> {
+    let regex = match source.strip_prefix("re:") {
+        Some(regex) => regex.to_string(),
+        None => {
+            let mut regex = String::from("^");
+            for c in source.chars() {
+                match c {
+                    '*' => regex.push_str(".*"),
+                    '?' => regex.push('.'),
+                    c => regex.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            regex.push('$');
+            regex
+        }
+    };
+    Regex::new(&regex).map_err(|e| invalid_data(format!("URL pattern {}: {}", source, e)))
+}
+
+// Decides on the target URI of a record whether it is processed at all, see
+// `Pipeline::accepts_uri`.
+#[derive(Debug, Default)]
+pub struct UrlFilter {
+    // if there are any, a URI must match one of them
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    // hosts blocked together with all their subdomains
+    blocked_hosts: HashSet<String>,
+}
+
+impl UrlFilter {
+    // Reads the rules of `path`, one per line: `+<pattern>` to include and
+    // `-<pattern>` to exclude URIs. Empty lines and `#` comments are skipped.
+    pub fn add_rules(&mut self, path: &Path) -> io::Result<()> {
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(source) = line.strip_prefix('+') {
+                self.include.push(pattern(source.trim())?);
+            } else if let Some(source) = line.strip_prefix('-') {
+                self.exclude.push(pattern(source.trim())?);
+            } else {
+                return Err(invalid_data(format!(
+                    "URL rule {} must start with + or -",
+                    line
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the blocked hosts of `path`, one per line.
+    pub fn add_blocklist(&mut self, path: &Path) -> io::Result<()> {
+        for line in fs::read_to_string(path)?.lines() {
+            let host = line.trim().trim_start_matches("*.").trim_end_matches('.');
+            if !host.is_empty() && !host.starts_with('#') {
+                self.blocked_hosts.insert(host.to_lowercase());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.blocked_hosts.is_empty()
+    }
+
+    // Checks `host` and every parent domain of it against the blocklist.
+    fn is_blocked(&self, host: &str) -> bool {
+        let mut host = host.trim_end_matches('.');
+        loop {
+            if self.blocked_hosts.contains(host) {
+                return true;
+            }
+            match host.find('.') {
+                Some(dot) => host = &host[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    // Returns the reason `uri` is rejected, `url-rules` or `blocked-host`.
+    pub fn check(&self, uri: &str) -> Option<&'static str> {
+        if !self.include.is_empty() && !self.include.iter().any(|regex| regex.is_match(uri)) {
+            return Some("url-rules");
+        }
+        if self.exclude.iter().any(|regex| regex.is_match(uri)) {
+            return Some("url-rules");
+        }
+        if !self.blocked_hosts.is_empty() {
+            let url = Url::parse(uri).ok();
+            if let Some(host) = url.as_ref().and_then(|url| url.host_str()) {
+                if self.is_blocked(&host.to_lowercase()) {
+                    return Some("blocked-host");
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A filter of the rules and blocked hosts files with `rules` and `hosts`.
+    fn filter(rules: &str, hosts: &str) -> UrlFilter {
+        let dir = tempfile::tempdir().unwrap();
+        let (rules_path, hosts_path) = (dir.path().join("rules"), dir.path().join("hosts"));
+        fs::write(&rules_path, rules).unwrap();
+        fs::write(&hosts_path, hosts).unwrap();
+        let mut urls = UrlFilter::default();
+        urls.add_rules(&rules_path).unwrap();
+        urls.add_blocklist(&hosts_path).unwrap();
+        urls
+    }
+
+    #[test]
+    fn globs_and_regexes() {
+        let glob = pattern("http://*.example.com/?").unwrap();
+        assert!(glob.is_match("http://www.example.com/a"));
+        assert!(!glob.is_match("http://www.example.com/ab"));
+        assert!(!glob.is_match("https://www.example.com/a"));
+        // `.` is no wildcard in globs
+        assert!(!glob.is_match("http://wwwxexample.com/a"));
+        // regexes are not anchored
+        let regex = pattern("re:/[0-9]+$").unwrap();
+        assert!(regex.is_match("http://example.com/12"));
+        assert!(!regex.is_match("http://example.com/12/a"));
+        let error = pattern("re:(").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn include_and_exclude_rules() {
+        let urls = filter("# comment\n\n+http://*.example.com/*\n-*/private/*\n", "");
+        assert_eq!(urls.check("http://www.example.com/a"), None);
+        assert_eq!(urls.check("http://other.org/"), Some("url-rules"));
+        assert_eq!(
+            urls.check("http://www.example.com/private/a"),
+            Some("url-rules")
+        );
+        // without include rules, only excluded URIs are rejected
+        let urls = filter("-*/private/*\n", "");
+        assert_eq!(urls.check("http://other.org/"), None);
+        assert_eq!(urls.check("http://other.org/private/"), Some("url-rules"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules");
+        fs::write(&path, "http://example.com/\n").unwrap();
+        let error = UrlFilter::default().add_rules(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn blocked_hosts_and_subdomains() {
+        let urls = filter("", "# comment\n*.blocked.com\nEvil.ORG.\n");
+        assert!(!urls.is_empty());
+        assert_eq!(urls.check("http://blocked.com/"), Some("blocked-host"));
+        assert_eq!(urls.check("http://a.b.blocked.com/x"), Some("blocked-host"));
+        assert_eq!(urls.check("http://EVIL.org./"), Some("blocked-host"));
+        assert_eq!(urls.check("http://notblocked.com/"), None);
+        assert_eq!(urls.check("http://blocked.com.example/"), None);
+        assert_eq!(urls.check("not a URI"), None);
+        // rules are checked first
+        let urls = filter("-*/a\n", "blocked.com\n");
+        assert_eq!(urls.check("http://blocked.com/a"), Some("url-rules"));
+        assert!(filter("", "").is_empty());
+    }
+}
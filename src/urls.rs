@@ -0,0 +1,121 @@
+use std;
+
+use url::Url;
+
+// The parts of a target URI that are indexed for filtering and aggregation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlParts {
+    pub scheme: String,
+    pub host: String,
+    // registered domain, public suffix plus one label, e.g. `bbc.co.uk`
+    pub domain: Option<String>,
+    // last label of the public suffix, e.g. `uk`
+    pub tld: Option<String>,
+}
+
+impl UrlParts {
+    pub fn parse(uri: &str) -> Option<UrlParts> {
+        let url = Url::parse(uri).ok()?;
+        let host = url.host_str()?.trim_end_matches('.').to_lowercase();
+        let (domain, tld) = match addr::parse_domain_name(&host) {
+            Ok(name) if name.has_known_suffix() => (
+                name.root().map(|root| root.to_string()),
+                name.suffix().rsplit('.').next().map(|tld| tld.to_string()),
+            ),
+            // IP addresses and hosts without a public suffix
+            _ => (None, None),
+        };
+        Some(UrlParts {
+            scheme: url.scheme().to_string(),
+            host,
+            domain,
+            tld,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::Value;
+
+    use crate::schema::{self, DocFields, SchemaOptions, SourceType};
+    use crate::warc::DocJson;
+
+    fn parts(host: &str, domain: Option<&str>, tld: Option<&str>) -> UrlParts {
+        UrlParts {
+            scheme: "http".to_string(),
+            host: host.to_string(),
+            domain: domain.map(|domain| domain.to_string()),
+            tld: tld.map(|tld| tld.to_string()),
+        }
+    }
+
+    #[test]
+    fn url_parts() {
+        let cases = [
+            // public suffix of two labels
+            (
+                "http://www.bbc.co.uk/news",
+                parts("www.bbc.co.uk", Some("bbc.co.uk"), Some("uk")),
+            ),
+            (
+                "http://News.BBC.co.uk./",
+                parts("news.bbc.co.uk", Some("bbc.co.uk"), Some("uk")),
+            ),
+            // ports are not part of the host
+            (
+                "http://example.com:8080/a?b=c",
+                parts("example.com", Some("example.com"), Some("com")),
+            ),
+            // IP addresses have neither domain nor TLD
+            ("http://192.168.0.1:8080/", parts("192.168.0.1", None, None)),
+            ("http://[::1]/", parts("[::1]", None, None)),
+            ("http://localhost/", parts("localhost", None, None)),
+            // IDN hosts are punycode encoded
+            (
+                "http://www.bücher.de/",
+                parts("www.xn--bcher-kva.de", Some("xn--bcher-kva.de"), Some("de")),
+            ),
+        ];
+        for (uri, expected) in cases.iter() {
+            assert_eq!(UrlParts::parse(uri).as_ref(), Some(expected), "{}", uri);
+        }
+        assert_eq!(
+            UrlParts::parse("https://example.org/").unwrap().scheme,
+            "https"
+        );
+        assert_eq!(UrlParts::parse("mailto:someone@example.com"), None);
+        assert_eq!(UrlParts::parse("www.example.com/no-scheme"), None);
+    }
+
+    #[test]
+    fn site_facet_path() {
+        let schema = schema::build_schema(SourceType::Warc, SchemaOptions::default());
+        let fields = DocFields::resolve(&schema).unwrap();
+        let site = |uri: &str| {
+            let parts = UrlParts::parse(uri).unwrap();
+            let document = fields.document(&DocJson {
+                uri: uri.to_string(),
+                host: Some(parts.host),
+                domain: parts.domain,
+                tld: parts.tld,
+                ..DocJson::default()
+            });
+            match document.get_first(fields.site.unwrap()) {
+                Some(Value::Facet(facet)) => Some(facet.to_string()),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            site("http://www.bbc.co.uk/news").as_deref(),
+            Some("/uk/bbc.co.uk/www.bbc.co.uk")
+        );
+        assert_eq!(
+            site("http://example.com/").as_deref(),
+            Some("/com/example.com/example.com")
+        );
+        // no facet without a domain
+        assert_eq!(site("http://192.168.0.1/"), None);
+    }
+}
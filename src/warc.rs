@@ -2,7 +2,9 @@ use std;
 use std::collections::HashMap;
 use std::io;
 use std::io::BufRead;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 
 extern crate tantivy;
 use derive_builder::Builder;
@@ -12,9 +14,17 @@ use std::io::Read;
 use tantivy::Document;
 use tantivy::Index;
 use tantivy::IndexWriter;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio::io::BufWriter;
+
+use crate::dates;
+use crate::decode;
+use crate::decode::Decoding;
+use crate::html;
+use crate::input;
+use crate::lang;
+use crate::pipeline::Pipeline;
+use crate::schema::DocFields;
+use crate::sink;
+use crate::sink::OutputOptions;
 
 #[derive(Debug)]
 enum WARCType {
@@ -115,40 +125,277 @@ fn read_record(reader: &mut dyn io::BufRead) -> Result<Option<WARCRecord>, std::
     return Ok(None);
 }
 
-#[derive(Debug, Serialize, Clone, Deserialize, Builder)]
+#[derive(Debug, Serialize, Clone, Deserialize, Builder, Default)]
 pub struct DocJson {
     pub uri: String,
+    // empty for PubMed articles without title or abstract
+    #[builder(default)]
     pub title: String,
+    #[builder(default)]
     pub body: String,
     pub date: String,
+    // WARC metadata, left out of the json when not known
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    // parts of `uri` besides the host, see `urls::UrlParts`
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tld: Option<String>,
+    // hash of the body, see `exact_dup`, and the record's WARC-Block-Digest
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_digest: Option<String>,
+    // SimHash of the body, see `near_dup`
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simhash: Option<u64>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<u64>,
+    // the input file and, for WARC, the record's offset in the uncompressed
+    // file, for reading the body back with `read_body_at`
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_offset: Option<u64>,
+    // dense vector of the title and body, see `embed`
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    // for passages, see `chunk`: the id of the document split, the passage's
+    // ordinal and its char offsets in that document's body
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passage: Option<u64>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passage_start: Option<u64>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passage_end: Option<u64>,
+}
+
+// Counts the bytes read from `inner`, which gives the offsets of the records.
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
+
+// The text of a record and how it was decoded.
+struct RecordText {
+    title: Option<String>,
+    body: String,
+    decoding: Decoding,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// The status, the headers by lower cased name and the body of an HTTP
+// response.
+fn http_response(payload: &[u8]) -> Option<(u16, HashMap<String, String>, &[u8])> {
+    let (head_end, body_start) = match find(payload, b"\r\n\r\n") {
+        Some(end) => (end, end + 4),
+        None => find(payload, b"\n\n").map(|end| (end, end + 2))?,
+    };
+    let head = String::from_utf8_lossy(&payload[..head_end]);
+    let mut lines = head.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    Some((status, headers, &payload[body_start..]))
+}
+
+// The text of a WET conversion record or of a WARC response record with a
+// successful HTML or plain text response, decoded in the charset of its
+// `Content-Type` or `<meta>` tag. `None` for all other records.
+fn record_text(record: &WARCRecord) -> Option<RecordText> {
+    match record.warc_type {
+        WARCType::Conversion => {
+            let (body, decoding) = decode::decode(&record.payload, None);
+            Some(RecordText {
+                title: None,
+                body,
+                decoding,
+            })
+        }
+        WARCType::Response => {
+            let (status, headers, body) = http_response(&record.payload)?;
+            let content_type = headers
+                .get("content-type")
+                .map_or("", |value| value.as_str());
+            let media_type = content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let is_html = matches!(media_type.as_str(), "text/html" | "application/xhtml+xml");
+            // still compressed, crawlers usually store the payload decoded
+            let encoded = headers
+                .get("content-encoding")
+                .map_or(false, |value| !value.eq_ignore_ascii_case("identity"));
+            if !(200..300).contains(&status) || encoded || !(is_html || media_type == "text/plain")
+            {
+                return None;
+            }
+            let charset = match decode::content_type_charset(content_type) {
+                Some(charset) => Some(charset.to_string()),
+                None if is_html => decode::meta_charset(body),
+                None => None,
+            };
+            let (text, decoding) = decode::decode(body, charset.as_deref());
+            if !is_html {
+                return Some(RecordText {
+                    title: None,
+                    body: text,
+                    decoding,
+                });
+            }
+            let page = html::extract(&text);
+            Some(RecordText {
+                title: page.title,
+                body: page.text,
+                decoding,
+            })
+        }
+        _ => None,
+    }
+}
+
+// WET records have no title, the start of the body stands in for it: a tenth
+// of it, at most 10 chars.
+fn title_of(body: &str) -> String {
+    let chars = body.chars().count() / 10;
+    let end = body
+        .char_indices()
+        .nth(chars.min(10))
+        .map_or(body.len(), |(i, _)| i);
+    body[..end].to_string()
+}
+
+// The body of the record starting `offset` bytes into the uncompressed WARC
+// file `path` as it was indexed, for snippets of indexes that do not store
+// bodies.
+pub fn read_body_at(path: &Path, offset: u64) -> io::Result<String> {
+    let mut bodies = read_bodies_at(path, &[offset])?;
+    bodies.remove(&offset).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no record at {}:{}", path.to_string_lossy(), offset),
+        )
+    })
+}
+
+// The bodies of the records at `offsets` in the WARC file `path` by offset,
+// read in a single pass, as compressed files can only be read from the start.
+// Offsets without a record with text are left out.
+pub fn read_bodies_at(path: &Path, offsets: &[u64]) -> io::Result<HashMap<u64, String>> {
+    let mut offsets = offsets.to_vec();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut reader = CountingReader {
+        inner: input::open(path, 64 * 1024)?,
+        position: 0,
+    };
+    let mut bodies = HashMap::new();
+    for offset in offsets {
+        // records may overlap only if the offsets are wrong
+        if offset < reader.position {
+            continue;
+        }
+        let gap = offset - reader.position;
+        let skipped = io::copy(&mut (&mut reader).take(gap), &mut io::sink())?;
+        if skipped != gap {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} ends before offset {}", path.to_string_lossy(), offset),
+            ));
+        }
+        if let Some(text) = read_record(&mut reader)?.and_then(|record| record_text(&record)) {
+            let mut body = text.body;
+            decode::repair(&mut body);
+            bodies.insert(offset, body);
+        }
+    }
+    Ok(bodies)
 }
 
 pub async fn extract_records_and_push_to_quickwit(
-    mut reader: impl BufRead + Send,
+    reader: impl BufRead + Send,
     out_file_path: PathBuf,
+    output: &OutputOptions,
+    pipeline: &Pipeline,
 ) -> io::Result<()> {
     let mut count = 0;
     let mut batch = Vec::new();
-    let out_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&out_file_path)
-        .await?;
-    let mut writer = BufWriter::new(out_file);
-    while let Some(record) = read_record(&mut reader)? {
-        if batch.len() > 10 {
+    let mut sink = sink::create(out_file_path, output, "WARC")?;
+    let batch_size = output.batch_size.unwrap_or(10);
+    let mut reader = CountingReader {
+        inner: reader,
+        position: 0,
+    };
+    loop {
+        let offset = reader.position;
+        let record = match read_record(&mut reader)? {
+            Some(record) => record,
+            None => break,
+        };
+        if batch.len() >= batch_size {
             // send to quickwit
             // send_to_quickwi(batch).await;
-            let docs = batch
-                .iter()
-                .map(|doc| serde_json::to_string(&doc).unwrap())
-                .collect::<Vec<String>>();
-            // join on newline
-            let blob = docs.join("\n");
-            let blob = blob.as_bytes();
-            writer.write(blob).await.unwrap();
-            writer.flush().await.unwrap();
+            pipeline.prepare_batch(&mut batch)?;
+            sink.write_batch(&batch)?;
             batch.clear();
         }
 
@@ -156,40 +403,52 @@ pub async fn extract_records_and_push_to_quickwit(
             WARCType::WarcInfo => {
                 //eprintln!("{}", String::from_utf8(record.payload).expect("warcinfo in UTF-8"));
             }
-            WARCType::Conversion => {
-                let body = String::from_utf8(record.payload).expect("convert to utf8 failed");
-                // create a json builder
-                // parse the body into a json object
+            WARCType::Conversion | WARCType::Response => {
                 count += 1;
                 if count % 1000 == 0 {
                     eprint!(".");
                 }
-                let body = body.to_string();
-                let uri = record
-                    .header
-                    .get("WARC-Target-URI")
-                    .expect("get uri")
-                    .to_string();
-                let count = body.chars().count() / 10;
-                let title_len = body
-                    .char_indices()
-                    .nth(std::cmp::min(10, count) as usize)
-                    .unwrap()
-                    .0;
-                let title = body.split_at_checked(title_len).unwrap().0.to_string();
-                let date = record
-                    .header
-                    .get("WARC-Date")
-                    .expect("get date")
-                    .to_string();
+                let (uri, warc_date) = match (
+                    record.header.get("WARC-Target-URI"),
+                    record.header.get("WARC-Date"),
+                ) {
+                    (Some(uri), Some(date)) => (uri.to_string(), date),
+                    _ => {
+                        let incomplete = &pipeline.stats.incomplete_records;
+                        incomplete.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                if !pipeline.accepts_uri(&uri) {
+                    continue;
+                }
+                let text = match record_text(&record) {
+                    Some(text) => text,
+                    None => continue,
+                };
+                pipeline.count_decoding(&text.decoding);
+                let body = text.body;
+                let title = text.title.unwrap_or_else(|| title_of(&body));
+                let date = dates::from_warc_date(warc_date).unwrap_or_default();
 
-                let doc = DocJson {
+                let mut doc = DocJson {
+                    record_id: record.header.get("WARC-Record-ID").cloned(),
+                    block_digest: record.header.get("WARC-Block-Digest").cloned(),
+                    language: record
+                        .header
+                        .get("WARC-Identified-Content-Language")
+                        .and_then(|value| lang::from_header(value)),
+                    content_length: Some(record.content_length as u64),
+                    source_offset: Some(offset),
                     uri,
                     title,
                     body,
                     date,
+                    ..Default::default()
                 };
-                batch.push(doc);
+                if pipeline.process(&mut doc)? {
+                    batch.push(doc);
+                }
                 // tx.send(doc).await.unwrap();
             }
             _ => {
@@ -199,17 +458,11 @@ pub async fn extract_records_and_push_to_quickwit(
     }
 
     if batch.len() > 0 {
-        let docs = batch
-            .iter()
-            .map(|doc| serde_json::to_string(&doc).unwrap())
-            .collect::<Vec<String>>();
-        // join on newline
-        let blob = docs.join("\n");
-        let blob = blob.as_bytes();
-        writer.write(blob).await.unwrap();
-        writer.flush().await.unwrap();
+        pipeline.prepare_batch(&mut batch)?;
+        sink.write_batch(&batch)?;
         batch.clear();
     }
+    sink.finish()?;
     println!("\nTotal Records of WARC file processed: {}", count);
     Ok(())
 }
@@ -233,11 +486,11 @@ pub fn extract_records_and_add_to_index(
     index_writer: &IndexWriter,
     reader: &mut dyn BufRead,
 ) -> io::Result<()> {
-    let schema = index.schema();
-    let schema_uri = schema.get_field("uri").unwrap();
-    let schema_title = schema.get_field("title").unwrap();
-    let schema_body = schema.get_field("body").unwrap();
-    let schema_date = schema.get_field("date").unwrap();
+    let fields = DocFields::resolve(&index.schema())?;
+    let schema_uri = fields.uri;
+    let schema_title = fields.title;
+    let schema_body = fields.body;
+    let schema_date = fields.date;
 
     let mut count = 0;
     while let Some(record) = read_record(reader)? {
@@ -245,24 +498,32 @@ pub fn extract_records_and_add_to_index(
             WARCType::WarcInfo => {
                 //eprintln!("{}", String::from_utf8(record.payload).expect("warcinfo in UTF-8"));
             }
-            WARCType::Conversion => {
+            WARCType::Conversion | WARCType::Response => {
+                let uri = match record.header.get("WARC-Target-URI") {
+                    Some(uri) => uri,
+                    None => continue,
+                };
+                let text = match record_text(&record) {
+                    Some(text) => text,
+                    None => continue,
+                };
                 count += 1;
                 if count % 1000 == 0 {
                     eprint!(".");
                 }
 
-                let body = std::str::from_utf8(&record.payload).expect("convert to utf8 failed");
+                let mut body = text.body;
+                decode::repair(&mut body);
                 let mut doc = Document::default();
-                doc.add_text(
-                    schema_uri,
-                    &record.header.get("WARC-Target-URI").expect("get uri"),
-                );
-                doc.add_text(
-                    schema_date,
-                    &record.header.get("WARC-Date").expect("get date"),
-                );
-                doc.add_text(schema_body, body);
-                doc.add_text(schema_title, body.lines().next().expect("title"));
+                doc.add_text(schema_uri, uri);
+                if let Some(date) = record.header.get("WARC-Date").and_then(|d| dates::parse(d)) {
+                    doc.add_date(schema_date, &date);
+                }
+                doc.add_text(schema_body, &body);
+                let title = text
+                    .title
+                    .unwrap_or_else(|| body.lines().next().unwrap_or_default().to_string());
+                doc.add_text(schema_title, title);
                 index_writer.add_document(doc);
             }
             _ => (),
@@ -8,9 +8,12 @@ use crate::warc::DocJson;
 use serde;
 use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio::io::BufWriter;
+
+use crate::dates;
+use crate::decode;
+use crate::pipeline::Pipeline;
+use crate::sink;
+use crate::sink::OutputOptions;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 struct Feed {
@@ -28,19 +31,27 @@ struct DocEntry {
 pub async fn extract_records_and_add_to_json(
     mut reader: impl BufRead + Send,
     path: PathBuf,
+    output: &OutputOptions,
+    pipeline: &Pipeline,
 ) -> io::Result<()> {
     // convert the path to wka.json
     let out_file_path = path.with_extension("wka.json");
-    let out_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&out_file_path)
-        .await?;
-    let mut writer = BufWriter::new(out_file);
-    let mut src = String::new();
-    reader.read_to_string(&mut src).expect("read file");
-    let feed: Feed = from_str(&src).unwrap();
+    let dump_date = dates::from_wikipedia_dump_name(&path);
+    let mut sink = sink::create(out_file_path, output, "WIKIPEDIA_ABSTRACT")?;
+    let batch_size = output.batch_size.unwrap_or(1000);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let charset = decode::xml_charset(&bytes);
+    let (src, decoding) = decode::decode(&bytes, charset.as_deref());
+    if decoding.lossy {
+        eprintln!(
+            "Invalid {} in {}, replaced by U+FFFD",
+            decoding.encoding.name(),
+            path.to_string_lossy()
+        );
+    }
+    let feed: Feed =
+        from_str(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
 
     let mut batch = Vec::new();
     let mut count = 0;
@@ -50,42 +61,36 @@ pub async fn extract_records_and_add_to_json(
             eprint!(".");
         }
 
-        if batch.len() > 1000 {
-            let docs = batch
-                .iter()
-                .map(|doc| serde_json::to_string(&doc).unwrap())
-                .collect::<Vec<String>>();
-            // join on newline
-            let blob = docs.join("\n");
-            let blob = blob.as_bytes();
-            writer.write(blob).await.unwrap();
-            writer.flush().await.unwrap();
+        if batch.len() >= batch_size {
+            pipeline.prepare_batch(&mut batch)?;
+            sink.write_batch(&batch)?;
             batch.clear();
         }
 
-        let doc = DocJson {
+        if !pipeline.accepts_uri(&doc_entry.url) {
+            continue;
+        }
+        let mut doc = DocJson {
             title: doc_entry.title,
             body: doc_entry.r#abstract,
             uri: doc_entry.url,
-            date: "".into(),
+            // abstract dumps have neither page ids nor revision timestamps
+            date: dump_date.clone().unwrap_or_default(),
+            ..Default::default()
         };
-        batch.push(doc);
+        if pipeline.process(&mut doc)? {
+            batch.push(doc);
+        }
     }
 
     if batch.len() > 0 {
         // send to quickwit
         // send_to_quickwi(batch).await;
-        let docs = batch
-            .iter()
-            .map(|doc| serde_json::to_string(&doc).unwrap())
-            .collect::<Vec<String>>();
-        // join on newline
-        let blob = docs.join("\n");
-        let blob = blob.as_bytes();
-        writer.write(blob).await.unwrap();
-        writer.flush().await.unwrap();
+        pipeline.prepare_batch(&mut batch)?;
+        sink.write_batch(&batch)?;
         batch.clear();
     }
+    sink.finish()?;
     println!("\nTotal Records of WARC file processed: {}", count);
     Ok(())
 }
@@ -0,0 +1,136 @@
+use docopt::Docopt;
+use tantivy_warc_indexer::config::JobConfig;
+
+// The options of the real usage that the tests override.
+const USAGE: &str = "
+Usage:
+  test [options] [<index> <warc_dir>]
+
+Options:
+  -s <source>
+  -t <threads>
+  --format <format>
+  --lang <codes>
+  --tasks <tasks>
+  --batch-size <docs>
+  --from <from>
+  --near-dup-bits <bits>
+  --upsert
+";
+
+fn args(argv: &[&str]) -> docopt::ArgvMap {
+    Docopt::new(USAGE)
+        .and_then(|d| d.argv(argv.iter()).parse())
+        .unwrap()
+}
+
+#[test]
+fn file_values_and_overrides() {
+    let dir = tempfile::tempdir().unwrap();
+    let job = format!(
+        "source = \"WARC\"\n\
+         inputs = \"{}\"\n\
+         index = \"out\"\n\
+         [sink]\n\
+         format = \"parquet\"\n\
+         [filters]\n\
+         lang = [\"eng\"]\n\
+         quality = [\"min-words=50\"]\n\
+         [limits]\n\
+         tasks = 4\n",
+        dir.path().to_string_lossy()
+    );
+    let path = dir.path().join("job.toml");
+    std::fs::write(&path, job).unwrap();
+
+    let mut config = JobConfig::load(&path).unwrap();
+    let errors = config.apply_args(&args(&["test", "--lang", "deu,fra", "--batch-size", "100"]));
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(config.validate().is_empty(), "{:?}", config.validate());
+    assert_eq!(config.sink.format, "parquet");
+    assert_eq!(config.filters.lang, ["deu", "fra"]);
+    assert_eq!(config.filters.quality, ["min-words=50"]);
+    assert_eq!(config.limits.tasks, 4);
+    assert_eq!(config.limits.threads, 4);
+    assert_eq!(config.limits.batch_size, Some(100));
+    assert_eq!(config.output_options().unwrap().batch_size, Some(100));
+
+    // Common Crawl's macrolanguage codes filter as whatlang's codes
+    assert!(config
+        .apply_args(&args(&["test", "--lang", "zho,nor"]))
+        .is_empty());
+    assert!(config.validate().is_empty(), "{:?}", config.validate());
+    let languages = config.pipeline().unwrap().languages.unwrap();
+    let mut languages: Vec<&str> = languages.iter().map(|code| code.as_str()).collect();
+    languages.sort_unstable();
+    assert_eq!(languages, ["cmn", "nob"]);
+}
+
+#[test]
+fn invalid_jobs_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("job.toml");
+    std::fs::write(&path, "source = \"WARC\"\nthreads = 4\n").unwrap();
+    // unknown keys are errors, not silently ignored
+    assert!(JobConfig::load(&path).is_err());
+
+    let mut config = JobConfig::default();
+    let errors = config.apply_args(&args(&[
+        "test",
+        "-s",
+        "NEWS",
+        "-t",
+        "x",
+        "--lang",
+        "xx",
+        "out",
+        "/nonexistent",
+    ]));
+    // numbers are reported by the option, not replaced by 0
+    assert_eq!(errors, ["-t x is not a number"]);
+    assert_eq!(config.limits.threads, JobConfig::default().limits.threads);
+    let errors = config.validate();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].contains("NEWS"));
+    assert!(errors[1].contains("/nonexistent"));
+    assert!(errors[2].contains("xx"));
+
+    let errors = config.apply_args(&args(&["test", "--near-dup-bits", "abc", "--from", "x"]));
+    assert_eq!(
+        errors,
+        [
+            "--from x is not a number",
+            "--near-dup-bits abc is not a number"
+        ]
+    );
+    assert_eq!(config.filters.near_dup_bits, None);
+    config.filters.near_dup_bits = Some(65);
+    config.from = Some(5);
+    config.to = Some(2);
+    let errors = config.validate();
+    assert!(
+        errors.iter().any(|e| e.contains("near_dup_bits 65")),
+        "{:?}",
+        errors
+    );
+    assert!(
+        errors.iter().any(|e| e.contains("from 5 is after to 2")),
+        "{:?}",
+        errors
+    );
+    config.sink.format = "parquet".to_string();
+    config.sink.max_bytes = Some(1 << 30);
+    assert!(config.validate().iter().any(|e| e.contains("not parquet")));
+    config.sink.max_bytes = None;
+
+    config.sink.embeddings_dir = Some("out/vectors".to_string());
+    assert!(config
+        .validate()
+        .iter()
+        .any(|e| e.contains("only used by format index")));
+    config.sink.format = "index".to_string();
+    assert!(config
+        .validate()
+        .iter()
+        .any(|e| e.contains("out/vectors is inside the index")));
+}
@@ -0,0 +1,457 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use tantivy::schema::Term;
+use tantivy::Index;
+use tantivy_warc_indexer::chunk::Chunker;
+use tantivy_warc_indexer::combine;
+use tantivy_warc_indexer::embed::Embedding;
+use tantivy_warc_indexer::index_sink::IndexTarget;
+use tantivy_warc_indexer::merge;
+use tantivy_warc_indexer::merge::MergePolicyOption;
+use tantivy_warc_indexer::pipeline::Pipeline;
+use tantivy_warc_indexer::schema;
+use tantivy_warc_indexer::schema::{BodyStore, DocFields, SchemaOptions, SourceType};
+use tantivy_warc_indexer::search::IndexSearcher;
+use tantivy_warc_indexer::segment;
+use tantivy_warc_indexer::sink::{OutputFormat, OutputOptions};
+use tantivy_warc_indexer::stats::{Count, IndexStats, StatsOptions};
+use tantivy_warc_indexer::tokenizers;
+use tantivy_warc_indexer::warc;
+use tantivy_warc_indexer::warc::DocJson;
+
+fn wet_file(first: usize, records: usize) -> String {
+    let mut wet = String::new();
+    for i in first..first + records {
+        let body = format!("Page {} about indexing common crawl files", i);
+        wet.push_str(&format!(
+            "WARC/1.0\r\n\
+             WARC-Type: conversion\r\n\
+             WARC-Target-URI: http://example.com/{}\r\n\
+             WARC-Date: 2020-04-10T14:25:56Z\r\n\
+             WARC-Record-ID: <urn:uuid:00000000-0000-0000-0000-{:012}>\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}\r\n\r\n",
+            i,
+            i,
+            body.len(),
+            body
+        ));
+    }
+    wet
+}
+
+// Indexes the files `inputs` of 10 records each, every one committed as a segment.
+async fn build_index(dir: &Path, inputs: std::ops::Range<usize>) {
+    let target = IndexTarget::open(
+        dir,
+        SourceType::Warc,
+        SchemaOptions::default(),
+        1,
+        15_000_000,
+        false,
+    )
+    .unwrap();
+    target.set_merge_policy(MergePolicyOption::None.policy());
+    let output = OutputOptions {
+        format: OutputFormat::Index,
+        index: Some(Arc::new(target)),
+        ..OutputOptions::default()
+    };
+    for input in inputs {
+        warc::extract_records_and_push_to_quickwit(
+            wet_file(input * 10, 10).as_bytes(),
+            dir.join("unused.json"),
+            &output,
+            &Pipeline::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
+
+fn segments_and_docs(dir: &Path) -> (usize, u64) {
+    let index = Index::open_in_dir(dir).unwrap();
+    let segments = index.searchable_segment_ids().unwrap().len();
+    let docs = index.reader().unwrap().searcher().num_docs();
+    (segments, docs)
+}
+
+// Creates an index in `dir` with the WARC schema and `options` holding `docs`.
+fn index_docs(dir: &Path, options: SchemaOptions, docs: &[DocJson]) -> Index {
+    let expected = schema::build_schema(SourceType::Warc, options);
+    let index = schema::open_or_create_index(dir, &expected).unwrap();
+    tokenizers::register(&index);
+    let fields = DocFields::resolve(&index.schema()).unwrap();
+    let mut writer = index.writer(15_000_000).unwrap();
+    for doc in docs {
+        writer.add_document(fields.document(doc));
+    }
+    writer.commit().unwrap();
+    index
+}
+
+#[tokio::test]
+async fn merge_and_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    build_index(dir.path(), 0..5).await;
+    assert_eq!(segments_and_docs(dir.path()), (5, 50));
+
+    merge::merge(dir.path(), Some(2), &MergePolicyOption::default())
+        .await
+        .unwrap();
+    assert_eq!(segments_and_docs(dir.path()), (2, 50));
+
+    merge::compact(dir.path()).await.unwrap();
+    assert_eq!(segments_and_docs(dir.path()), (1, 50));
+}
+
+#[tokio::test]
+async fn embeddings_go_next_to_the_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let index = dir.path().join("index");
+    let target = IndexTarget::open(
+        &index,
+        SourceType::Warc,
+        SchemaOptions::default(),
+        1,
+        15_000_000,
+        false,
+    )
+    .unwrap();
+    let mut output = OutputOptions {
+        format: OutputFormat::Index,
+        index: Some(Arc::new(target)),
+        ..OutputOptions::default()
+    };
+    let mut pipeline = Pipeline::default();
+    pipeline.embedding = Some(Arc::new(Embedding::parse("hash,dims=8").unwrap()));
+    let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+    for embeddings_dir in [None, Some(dir.path().join("vectors"))].iter() {
+        output.embeddings_dir = embeddings_dir.clone();
+        warc::extract_records_and_push_to_quickwit(
+            wet_file(0, 3).as_bytes(),
+            index.join("test.warc"),
+            &output,
+            &pipeline,
+        )
+        .await
+        .unwrap();
+    }
+    // by default in `<index>.embeddings`, never in the index directory
+    assert_eq!(
+        lines(&dir.path().join("index.embeddings/test.embeddings.json")),
+        3
+    );
+    assert!(!index.join("test.embeddings.json").exists());
+    assert_eq!(lines(&dir.path().join("vectors/test.embeddings.json")), 3);
+}
+
+#[tokio::test]
+async fn combine_shards_without_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, second) = (dir.path().join("0"), dir.path().join("1"));
+    build_index(&first, 0..3).await;
+    // records 20 to 29 are in both shards
+    build_index(&second, 2..5).await;
+
+    let combined = dir.path().join("combined");
+    combine::run(&combined, &[&first, &second], 1, 15_000_000).unwrap();
+    assert_eq!(segments_and_docs(&combined).1, 50);
+    // the result is a new index
+    assert!(combine::run(&combined, &[&first], 1, 15_000_000).is_err());
+
+    // bodies that are not stored cannot be combined
+    let options = SchemaOptions {
+        body_store: BodyStore::None,
+        ..SchemaOptions::default()
+    };
+    let doc = DocJson {
+        uri: "http://example.com/".to_string(),
+        body: "a body".to_string(),
+        ..DocJson::default()
+    };
+    let unstored = dir.path().join("unstored");
+    index_docs(&unstored, options, &[doc]);
+    let target = dir.path().join("from-unstored");
+    let error = combine::run(&target, &[&unstored], 1, 15_000_000).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(!target.join("meta.json").exists());
+}
+
+#[test]
+fn phrases_within_sentences() {
+    let body = "Dr. Smith moved to New. York is big.\nA new york pizza";
+    let sentences: Vec<&str> = segment::sentences(body)
+        .into_iter()
+        .map(|sentence| &body[sentence])
+        .collect();
+    assert_eq!(
+        sentences,
+        [
+            "Dr. Smith moved to New.",
+            "York is big.",
+            "A new york pizza"
+        ]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = SchemaOptions {
+        sentence_positions: true,
+        ..SchemaOptions::default()
+    };
+    let docs: Vec<DocJson> = ["moved to New. York is big.", "a New York pizza"]
+        .iter()
+        .enumerate()
+        .map(|(i, body)| DocJson {
+            uri: format!("http://example.com/{}", i),
+            body: body.to_string(),
+            ..DocJson::default()
+        })
+        .collect();
+    index_docs(dir.path(), options, &docs);
+
+    let searcher = IndexSearcher::open(dir.path()).unwrap();
+    let (count, hits) = searcher.search("body:\"new york\"", 10).unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(hits[0].uri, "http://example.com/1");
+    assert_eq!(searcher.search("new AND york", 10).unwrap().0, 2);
+}
+
+#[test]
+fn merge_groups_balance_documents() {
+    let groups = merge::merge_groups(vec![("a", 10), ("b", 60), ("c", 30), ("d", 20)], 2);
+    assert_eq!(groups, [vec!["b"], vec!["c", "d", "a"]]);
+    assert_eq!(merge::merge_groups(vec![("a", 1)], 3), [vec!["a"]]);
+}
+
+#[test]
+fn merge_policies_parse() {
+    assert_eq!(
+        MergePolicyOption::parse("log,min-segments=4"),
+        Ok(MergePolicyOption::Log {
+            min_segments: Some(4),
+            max_docs: None
+        })
+    );
+    assert_eq!(
+        MergePolicyOption::parse("none"),
+        Ok(MergePolicyOption::None)
+    );
+    assert!(MergePolicyOption::parse("log,min-segments=0").is_err());
+    assert!(MergePolicyOption::parse("tiered").is_err());
+}
+
+#[test]
+fn stats_of_index() {
+    // hosts have a single label before their domain
+    let doc = |i: usize, host: &str, language: &str, date: &str| DocJson {
+        uri: format!("http://{}/{}", host, i),
+        body: format!("page {} of {}", i, host),
+        date: date.to_string(),
+        host: Some(host.to_string()),
+        domain: host.split_once('.').map(|(_, domain)| domain.to_string()),
+        tld: host.rsplit('.').next().map(|tld| tld.to_string()),
+        language: Some(language.to_string()),
+        ..DocJson::default()
+    };
+    let docs = [
+        doc(0, "a.example.com", "eng", "2020-04-10T14:25:56Z"),
+        doc(1, "b.example.com", "eng", "2020-04-11T08:00:00Z"),
+        doc(2, "www.example.org", "deu", "2021-01-02T00:00:00Z"),
+        // no date, must not be counted in 1970-01
+        doc(3, "www.example.com", "eng", ""),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+    let index = index_docs(dir.path(), SchemaOptions::default(), &docs);
+    let searcher = index.reader().unwrap().searcher();
+    let options = StatsOptions {
+        top: 1,
+        term_fields: vec!["body".to_string()],
+    };
+    let stats = IndexStats::collect(&index, &searcher, &options).unwrap();
+    let counts = |counts: &[Count]| -> Vec<(String, u64)> {
+        counts.iter().map(|c| (c.value.clone(), c.docs)).collect()
+    };
+
+    assert_eq!(stats.docs, 4);
+    assert_eq!(
+        counts(&stats.months),
+        [("2020-04".to_string(), 2), ("2021-01".to_string(), 1)]
+    );
+    assert_eq!(counts(&stats.domains), [("example.com".to_string(), 3)]);
+    assert_eq!(counts(&stats.tlds), [("com".to_string(), 3)]);
+    assert_eq!(counts(&stats.languages), [("eng".to_string(), 3)]);
+
+    let field = |name: &str| stats.fields.iter().find(|f| f.name == name).unwrap();
+    assert!(field("body").bytes > 0);
+    // "page", the number, "of" and the three parts of the host
+    assert_eq!(field("body").tokens, Some(4 * 6));
+    assert!(field("body").terms.unwrap() > 0);
+    assert!(field("uri").bytes > 0);
+    // counted for the term fields only
+    assert_eq!(field("uri").terms, None);
+    assert_eq!(stats.fields.len(), index.schema().fields().count());
+    assert!(stats.store_bytes > 0);
+    // deleted documents count in neither languages nor months
+    let uri = index.schema().get_field("uri").unwrap();
+    let mut writer = index.writer(15_000_000).unwrap();
+    writer.delete_term(Term::from_field_text(uri, "http://a.example.com/0"));
+    writer.commit().unwrap();
+    let searcher = index.reader().unwrap().searcher();
+    let stats = IndexStats::collect(&index, &searcher, &options).unwrap();
+    assert_eq!(stats.deleted, 1);
+    assert_eq!(counts(&stats.languages), [("eng".to_string(), 2)]);
+    assert_eq!(
+        counts(&stats.months),
+        [("2020-04".to_string(), 1), ("2021-01".to_string(), 1)]
+    );
+}
+
+#[test]
+fn german_documents_stemmed() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = SchemaOptions {
+        multilingual: true,
+        ..SchemaOptions::default()
+    };
+    let docs = [
+        DocJson {
+            uri: "http://example.de/".to_string(),
+            title: "Spielplatz".to_string(),
+            body: "Die Kinder spielen im Garten".to_string(),
+            language: Some("deu".to_string()),
+            ..DocJson::default()
+        },
+        DocJson {
+            uri: "http://example.com/".to_string(),
+            body: "The children are playing in the garden".to_string(),
+            language: Some("eng".to_string()),
+            ..DocJson::default()
+        },
+    ];
+    index_docs(dir.path(), options, &docs);
+
+    let searcher = IndexSearcher::open(dir.path()).unwrap();
+    // both stem to "spiel" with the German stemmer only
+    let (count, hits) = searcher.search("body_deu:spiele", 10).unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(hits[0].uri, "http://example.de/");
+    assert_eq!(searcher.search("spiele", 10).unwrap().0, 1);
+    // the German body is not in the English field
+    assert_eq!(searcher.search("body:spielen", 10).unwrap().0, 0);
+    assert_eq!(searcher.search("body:play", 10).unwrap().0, 1);
+    assert_eq!(searcher.search("body_deu:play", 10).unwrap().0, 0);
+}
+
+#[test]
+fn multilingual_hits_have_titles() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = SchemaOptions {
+        multilingual: true,
+        ..SchemaOptions::default()
+    };
+    let docs = [
+        DocJson {
+            uri: "http://example.de/".to_string(),
+            title: "Spielplatz".to_string(),
+            body: "Die Kinder spielen im Garten".to_string(),
+            language: Some("deu".to_string()),
+            ..DocJson::default()
+        },
+        DocJson {
+            uri: "http://example.com/".to_string(),
+            title: "Playground".to_string(),
+            body: "The children are playing in the garden".to_string(),
+            ..DocJson::default()
+        },
+    ];
+    index_docs(dir.path(), options, &docs);
+
+    let searcher = IndexSearcher::open(dir.path()).unwrap();
+    let (_, hits) = searcher.search("spielen", 10).unwrap();
+    assert_eq!(hits[0].title, "Spielplatz");
+    let (_, hits) = searcher.search("garden", 10).unwrap();
+    assert_eq!(hits[0].title, "Playground");
+}
+
+#[test]
+fn schema_created_or_checked() {
+    let dir = tempfile::tempdir().unwrap();
+    // an existing but empty directory and one not there yet
+    let empty = dir.path().join("empty");
+    std::fs::create_dir(&empty).unwrap();
+    let missing = dir.path().join("missing").join("index");
+    let expected = schema::build_schema(SourceType::Warc, SchemaOptions::default());
+    for index_dir in [&empty, &missing].iter() {
+        let index = schema::open_or_create_index(index_dir, &expected).unwrap();
+        assert!(schema::mismatches(&index.schema(), &expected).is_empty());
+        assert!(index_dir.join("meta.json").exists());
+        // opened with the same schema again
+        schema::open_or_create_index(index_dir, &expected).unwrap();
+    }
+
+    let options = SchemaOptions {
+        body_store: BodyStore::None,
+        passages: true,
+        ..SchemaOptions::default()
+    };
+    let other = schema::build_schema(SourceType::Warc, options);
+    let error = schema::open_or_create_index(&empty, &other).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    let message = error.to_string();
+    let lines: Vec<&str> = message.lines().map(|line| line.trim()).collect();
+    // stored before, not now
+    assert!(lines.iter().any(|line| line.starts_with("field body is")));
+    for field in ["source_file", "source_offset", "parent_id", "passage"].iter() {
+        assert!(lines.contains(&format!("missing field {}", field).as_str()));
+    }
+    assert!(!message.contains("field title"));
+    assert_eq!(schema::mismatches(&expected, &other).len(), 1 + 2 + 4);
+    // fields only the existing index has are fine
+    let entrez = schema::build_schema(SourceType::Entrez, options);
+    assert!(schema::mismatches(&other, &entrez).is_empty());
+}
+
+// Indexes the same file twice with `--upsert`, returning the documents after
+// each run.
+async fn upsert_twice(dir: &Path, chunker: Option<Chunker>) -> (u64, u64) {
+    let options = SchemaOptions {
+        passages: chunker.is_some(),
+        ..SchemaOptions::default()
+    };
+    let target = IndexTarget::open(dir, SourceType::Warc, options, 1, 15_000_000, true).unwrap();
+    let output = OutputOptions {
+        format: OutputFormat::Index,
+        index: Some(Arc::new(target)),
+        ..OutputOptions::default()
+    };
+    let mut pipeline = Pipeline::default();
+    pipeline.chunker = chunker.map(Arc::new);
+    let mut docs = Vec::new();
+    for _ in 0..2 {
+        warc::extract_records_and_push_to_quickwit(
+            wet_file(0, 5).as_bytes(),
+            dir.join("unused.json"),
+            &output,
+            &pipeline,
+        )
+        .await
+        .unwrap();
+        docs.push(segments_and_docs(dir).1);
+    }
+    (docs[0], docs[1])
+}
+
+#[tokio::test]
+async fn upsert_replaces_documents_and_passages() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(upsert_twice(&dir.path().join("whole"), None).await, (5, 5));
+    // 7 words in passages of 3 overlapping by 1, the first passage deletes
+    // all passages of the earlier copy
+    let chunker = Chunker::parse("words=3,overlap=1").unwrap();
+    let passages = dir.path().join("passages");
+    assert_eq!(upsert_twice(&passages, Some(chunker)).await, (15, 15));
+}
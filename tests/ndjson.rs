@@ -0,0 +1,673 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use flate2::read::MultiGzDecoder;
+use tantivy_warc_indexer::chunk;
+use tantivy_warc_indexer::chunk::Chunker;
+use tantivy_warc_indexer::embed::Embedding;
+use tantivy_warc_indexer::exact_dup::SeenSet;
+use tantivy_warc_indexer::near_dup::{NearDup, NearDupMode};
+use tantivy_warc_indexer::pipeline::Pipeline;
+use tantivy_warc_indexer::pubmed;
+use tantivy_warc_indexer::quality::QualityFilter;
+use tantivy_warc_indexer::sink::{OutputCompression, OutputOptions};
+use tantivy_warc_indexer::url_filter::UrlFilter;
+use tantivy_warc_indexer::warc;
+use tantivy_warc_indexer::warc::DocJson;
+use tantivy_warc_indexer::wikipedia_abstract;
+
+fn wet_record(warc_type: &str, id: usize, uri: &str, body: &str) -> String {
+    format!(
+        "WARC/1.0\r\n\
+         WARC-Type: {}\r\n\
+         WARC-Target-URI: {}\r\n\
+         WARC-Date: 2020-04-10T14:25:56Z\r\n\
+         WARC-Record-ID: <urn:uuid:00000000-0000-0000-0000-{:012}>\r\n\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\
+         \r\n\
+         {}\r\n\r\n",
+        warc_type,
+        uri,
+        id,
+        body.len(),
+        body
+    )
+}
+
+fn example_uri(i: usize) -> String {
+    format!("http://example.com/{}", i)
+}
+
+// A warcinfo record followed by a conversion record per `(uri, body)` page.
+fn wet_pages<U: AsRef<str>, B: AsRef<str>>(pages: impl IntoIterator<Item = (U, B)>) -> String {
+    let mut wet = wet_record("warcinfo", 0, "", "software: test");
+    for (i, (uri, body)) in pages.into_iter().enumerate() {
+        wet.push_str(&wet_record(
+            "conversion",
+            i + 1,
+            uri.as_ref(),
+            body.as_ref(),
+        ));
+    }
+    wet
+}
+
+// Pages of `http://example.com/<i>` with the `i`-th of `bodies`.
+fn wet_bodies<B: AsRef<str>>(bodies: &[B]) -> String {
+    wet_pages(
+        bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| (example_uri(i), body)),
+    )
+}
+
+fn wet_file(records: usize) -> String {
+    let bodies: Vec<String> = (0..records)
+        .map(|i| format!("Page {}\nsecond line with \"quotes\"\nthird line", i))
+        .collect();
+    wet_bodies(&bodies)
+}
+
+fn wikipedia_file(docs: usize) -> String {
+    let mut xml = String::from("<feed>\n");
+    for i in 0..docs {
+        xml.push_str(&format!(
+            "<doc><title>Wikipedia: Article {}</title>\
+             <url>https://en.wikipedia.org/wiki/Article_{}</url>\
+             <abstract>Abstract of article {}.</abstract></doc>\n",
+            i, i, i
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn pubmed_file(articles: usize) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" ?>\n<PubmedArticleSet>\n");
+    for i in 0..articles {
+        xml.push_str(&format!(
+            "<PubmedArticle><MedlineCitation Status=\"MEDLINE\" Owner=\"NLM\">\
+             <PMID Version=\"1\">{}</PMID>\
+             <Article PubModel=\"Print\"><ArticleTitle>Title {}</ArticleTitle>\
+             <Abstract><AbstractText>Abstract {}</AbstractText></Abstract>\
+             </Article></MedlineCitation></PubmedArticle>\n",
+            i + 1,
+            i,
+            i
+        ));
+    }
+    xml.push_str("</PubmedArticleSet>\n");
+    xml
+}
+
+// Every line must be one complete document and the stream must end in `\n`.
+fn read_ndjson(mut reader: impl Read) -> Vec<DocJson> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).unwrap();
+    assert!(text.is_empty() || text.ends_with('\n'));
+    for line in text.lines() {
+        serde_json::from_str::<DocJson>(line).expect("one document per line");
+    }
+    serde_json::Deserializer::from_str(&text)
+        .into_iter::<DocJson>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
+fn read_ndjson_file(path: &Path) -> Vec<DocJson> {
+    read_ndjson(File::open(path).unwrap())
+}
+
+// Runs the WARC `records` through `pipeline` and reads back the documents written.
+async fn run_pipeline(records: &[u8], pipeline: &Pipeline) -> Vec<DocJson> {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("test.warc.json");
+    warc::extract_records_and_push_to_quickwit(
+        records,
+        out.clone(),
+        &OutputOptions::default(),
+        pipeline,
+    )
+    .await
+    .unwrap();
+    read_ndjson_file(&out)
+}
+
+async fn run_warc(records: usize, out: &Path, output: &OutputOptions) -> io::Result<()> {
+    let wet = wet_file(records);
+    warc::extract_records_and_push_to_quickwit(
+        wet.as_bytes(),
+        out.to_path_buf(),
+        output,
+        &Pipeline::default(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn warc_round_trip_across_batches() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("test.warc.json");
+    run_warc(25, &out, &OutputOptions::default()).await.unwrap();
+
+    let docs = read_ndjson_file(&out);
+    assert_eq!(docs.len(), 25);
+    for (i, doc) in docs.iter().enumerate() {
+        assert_eq!(doc.uri, example_uri(i));
+        assert!(doc.body.starts_with(&format!("Page {}\n", i)));
+        assert_eq!(doc.date, "2020-04-10T14:25:56Z");
+    }
+}
+
+#[tokio::test]
+async fn rerun_truncates_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("test.warc.json");
+    run_warc(30, &out, &OutputOptions::default()).await.unwrap();
+    run_warc(3, &out, &OutputOptions::default()).await.unwrap();
+
+    assert_eq!(read_ndjson_file(&out).len(), 3);
+}
+
+#[tokio::test]
+async fn rolled_parts_and_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("test.warc.json");
+    let output = OutputOptions {
+        compression: OutputCompression::Gzip,
+        max_docs: Some(7),
+        ..OutputOptions::default()
+    };
+    run_warc(25, &out, &output).await.unwrap();
+
+    let manifest: serde_json::Value =
+        serde_json::from_reader(File::open(dir.path().join("test.warc.manifest.json")).unwrap())
+            .unwrap();
+    let parts = manifest["parts"].as_array().unwrap();
+    assert_eq!(parts.len(), 4);
+
+    let mut uris = Vec::new();
+    for part in parts {
+        let path = dir.path().join(part["path"].as_str().unwrap());
+        let docs = read_ndjson(MultiGzDecoder::new(File::open(path).unwrap()));
+        assert!(docs.len() <= 7);
+        assert_eq!(part["docs"].as_u64().unwrap(), docs.len() as u64);
+        uris.extend(docs.into_iter().map(|doc| doc.uri));
+    }
+    assert_eq!(uris, (0..25).map(example_uri).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn rerun_removes_stale_parts() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("test.warc.json");
+    let output = OutputOptions {
+        max_docs: Some(7),
+        ..OutputOptions::default()
+    };
+    run_warc(25, &out, &output).await.unwrap();
+    let part = |i: usize| dir.path().join(format!("test.warc.{:05}.json", i));
+    assert!(part(3).exists());
+    run_warc(10, &out, &output).await.unwrap();
+
+    assert_eq!(read_ndjson_file(&part(0)).len(), 7);
+    assert_eq!(read_ndjson_file(&part(1)).len(), 3);
+    assert!(!part(2).exists());
+    assert!(!part(3).exists());
+    let manifest: serde_json::Value =
+        serde_json::from_reader(File::open(dir.path().join("test.warc.manifest.json")).unwrap())
+            .unwrap();
+    assert_eq!(manifest["parts"].as_array().unwrap().len(), 2);
+}
+
+// Three copies of one page, one of them with an extra word, and an unrelated page.
+async fn run_near_dup(mode: NearDupMode) -> Vec<DocJson> {
+    let page: String = (0..40)
+        .map(|i| format!("Paragraph {} of a long article about foxes and dogs. ", i))
+        .collect();
+    let bodies = [
+        page.clone(),
+        format!("{} today", page),
+        "Completely unrelated text about compilers, parsers and type checkers".to_string(),
+        page,
+    ];
+    let wet = wet_bodies(&bodies);
+    let mut pipeline = Pipeline::default();
+    pipeline.near_dup = Some(Arc::new(NearDup::new(mode, 3)));
+    run_pipeline(wet.as_bytes(), &pipeline).await
+}
+
+#[tokio::test]
+async fn near_duplicates_dropped_or_tagged() {
+    let docs = run_near_dup(NearDupMode::Drop).await;
+    let uris: Vec<&str> = docs.iter().map(|doc| doc.uri.as_str()).collect();
+    assert_eq!(uris, ["http://example.com/0", "http://example.com/2"]);
+
+    let docs = run_near_dup(NearDupMode::Tag).await;
+    assert_eq!(docs.len(), 4);
+    let first = docs[0].simhash.unwrap();
+    assert_eq!(docs[0].duplicate_of, None);
+    assert_eq!(docs[1].duplicate_of, Some(first));
+    assert_eq!(docs[2].duplicate_of, None);
+    assert_eq!(docs[3].duplicate_of, Some(first));
+    assert_eq!(docs[3].simhash, Some(first));
+}
+
+// Runs `wet_file(records)` named `name` through a pipeline using the seen-set `seen`.
+async fn run_dedup(seen: &Path, name: &str, records: usize) -> Vec<DocJson> {
+    let mut pipeline = Pipeline::default();
+    pipeline.seen = Some(Arc::new(SeenSet::open(seen).unwrap()));
+    let pipeline = pipeline.for_input(name).unwrap();
+    let docs = run_pipeline(wet_file(records).as_bytes(), &pipeline).await;
+    pipeline.flush().unwrap();
+    docs
+}
+
+#[tokio::test]
+async fn exact_duplicates_across_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let seen = dir.path().join("seen.bin");
+
+    let docs = run_dedup(&seen, "a.warc.wet", 10).await;
+    assert_eq!(docs.len(), 10);
+    assert!(docs.iter().all(|doc| doc.content_hash.is_some()));
+    // running over the same input again writes the same documents
+    assert_eq!(run_dedup(&seen, "a.warc.wet", 10).await.len(), 10);
+    // another input only adds the documents not seen before
+    let docs = run_dedup(&seen, "b.warc.wet", 15).await;
+    let uris: Vec<String> = docs.into_iter().map(|doc| doc.uri).collect();
+    assert_eq!(uris, (10..15).map(example_uri).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn record_offsets_read_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let wet = dir.path().join("test.warc.wet.gz");
+    let mut encoder =
+        flate2::write::GzEncoder::new(File::create(&wet).unwrap(), flate2::Compression::default());
+    io::Write::write_all(&mut encoder, wet_file(5).as_bytes()).unwrap();
+    encoder.finish().unwrap();
+    let out = dir.path().join("test.warc.json");
+    warc::extract_records_and_push_to_quickwit(
+        io::BufReader::new(MultiGzDecoder::new(File::open(&wet).unwrap())),
+        out.clone(),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+
+    let docs = read_ndjson_file(&out);
+    assert_eq!(docs.len(), 5);
+    for doc in &docs {
+        let body = warc::read_body_at(&wet, doc.source_offset.unwrap()).unwrap();
+        assert_eq!(body, doc.body);
+    }
+    // in a single pass, in any order and with repeated offsets
+    let offsets: Vec<u64> = docs
+        .iter()
+        .rev()
+        .chain(&docs[1..2])
+        .map(|doc| doc.source_offset.unwrap())
+        .collect();
+    let bodies = warc::read_bodies_at(&wet, &offsets).unwrap();
+    assert_eq!(bodies.len(), 5);
+    for doc in &docs {
+        assert_eq!(bodies[&doc.source_offset.unwrap()], doc.body);
+    }
+}
+
+fn raw_record(warc_type: &str, id: usize, payload: &[u8]) -> Vec<u8> {
+    let mut record = format!(
+        "WARC/1.0\r\n\
+         WARC-Type: {}\r\n\
+         WARC-Target-URI: http://example.com/{}\r\n\
+         WARC-Date: 2020-04-10T14:25:56Z\r\n\
+         WARC-Record-ID: <urn:uuid:00000000-0000-0000-0000-{:012}>\r\n\
+         Content-Length: {}\r\n\
+         \r\n",
+        warc_type,
+        id,
+        id,
+        payload.len()
+    )
+    .into_bytes();
+    record.extend_from_slice(payload);
+    record.extend_from_slice(b"\r\n\r\n");
+    record
+}
+
+#[tokio::test]
+async fn charsets_decoded_and_repaired() {
+    let mut warc = raw_record(
+        "response",
+        0,
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n\
+          <html><head><meta charset=\"windows-1252\"><title>Caf\xe9</title></head>\
+          <body><p>Cr\xe8me br\xfbl\xe9e</p><script>run()</script><p>Tarte &amp; caf\xe9</p>",
+    );
+    warc.extend(raw_record(
+        "response",
+        1,
+        b"HTTP/1.1 404 Not Found\r\nContent-Type: text/html\r\n\r\ngone",
+    ));
+    warc.extend(raw_record(
+        "conversion",
+        2,
+        "caf\u{c3}\u{a9} cr\u{c3}\u{a8}me".as_bytes(),
+    ));
+    warc.extend(raw_record("conversion", 3, b"broken \xff text"));
+    warc.extend(raw_record("conversion", 4, "cafe\u{301}".as_bytes()));
+    // without URI or date, skipped and counted
+    for (id, header) in [(5, "WARC-Target-URI"), (6, "WARC-Date")].iter() {
+        let record = String::from_utf8(raw_record("conversion", *id, b"incomplete")).unwrap();
+        let line = record
+            .lines()
+            .find(|line| line.starts_with(header))
+            .unwrap();
+        warc.extend(record.replace(&format!("{}\r\n", line), "").into_bytes());
+    }
+    let pipeline = Pipeline::default();
+    let docs = run_pipeline(&warc, &pipeline).await;
+    let bodies: Vec<&str> = docs.iter().map(|doc| doc.body.as_str()).collect();
+    assert_eq!(
+        bodies,
+        [
+            "Cr\u{e8}me br\u{fb}l\u{e9}e\nTarte & caf\u{e9}",
+            "caf\u{e9} cr\u{e8}me",
+            "broken \u{fffd} text",
+            "caf\u{e9}",
+        ]
+    );
+    assert_eq!(docs[0].title, "Caf\u{e9}");
+    let stats = &pipeline.stats;
+    assert_eq!(stats.recoded.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.mojibake_repaired.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.lossy_decoded.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.normalized.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.incomplete_records.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn overlapping_passages() {
+    let mut pipeline = Pipeline::default();
+    pipeline.chunker = Some(Arc::new(Chunker::parse("words=4,overlap=1").unwrap()));
+    // 8 words in passages starting at words 0, 3 and 6
+    let docs = run_pipeline(wet_file(2).as_bytes(), &pipeline).await;
+    assert_eq!(docs.len(), 6);
+    let body = "Page 0\nsecond line with \"quotes\"\nthird line";
+    let parent = "<urn:uuid:00000000-0000-0000-0000-000000000001>";
+    let expected = [
+        "Page 0\nsecond line",
+        "line with \"quotes\"\nthird",
+        "third line",
+    ];
+    for (i, doc) in docs[..3].iter().enumerate() {
+        assert_eq!(doc.body, expected[i]);
+        assert_eq!(doc.parent_id.as_deref(), Some(parent));
+        assert_eq!(doc.record_id, Some(format!("{}#{}", parent, i)));
+        assert_eq!(doc.passage, Some(i as u64));
+        let (start, end) = (doc.passage_start.unwrap(), doc.passage_end.unwrap());
+        assert_eq!(chunk::passage_text(body, start, end), doc.body);
+    }
+    assert_eq!(pipeline.stats.passages.load(Ordering::Relaxed), 6);
+    assert!(Chunker::parse("sentences=2,overlap=2").is_err());
+}
+
+#[tokio::test]
+async fn hash_embeddings_added() {
+    let mut pipeline = Pipeline::default();
+    pipeline.embedding = Some(Arc::new(Embedding::parse("hash,dims=64").unwrap()));
+    let docs = run_pipeline(wet_file(3).as_bytes(), &pipeline).await;
+    assert_eq!(docs.len(), 3);
+    for doc in docs {
+        let embedding = doc.embedding.unwrap();
+        assert_eq!(embedding.len(), 64);
+        let norm: f32 = embedding.iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-4);
+    }
+    assert!(Embedding::parse("hash,model=bge").is_err());
+    assert!(Embedding::parse("onnx").is_err());
+}
+
+#[tokio::test]
+async fn quality_filters_count_drops() {
+    let bodies = [
+        "A sentence with enough words to pass the word count of this test.",
+        "Too short.",
+        "1234 5678 9012 3456 7890 1234 5678 9012 3456 7890 1234 5678 !!",
+        "Another sentence with enough words to pass the word count rule.",
+    ];
+    let wet = wet_bodies(&bodies);
+    let mut pipeline = Pipeline::default();
+    pipeline.quality = Some(Arc::new(vec![
+        QualityFilter::parse("min-words=5").unwrap(),
+        QualityFilter::parse("min-alpha-ratio=0.5").unwrap(),
+    ]));
+    let uris: Vec<String> = run_pipeline(wet.as_bytes(), &pipeline)
+        .await
+        .into_iter()
+        .map(|doc| doc.uri)
+        .collect();
+    assert_eq!(uris, ["http://example.com/0", "http://example.com/3"]);
+    let filtered = pipeline.stats.filtered.lock().unwrap();
+    assert_eq!(filtered.get("min-words"), Some(&1));
+    assert_eq!(filtered.get("min-alpha-ratio"), Some(&1));
+}
+
+#[tokio::test]
+async fn url_rules_and_blocked_hosts() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.txt");
+    let hosts = dir.path().join("hosts.txt");
+    std::fs::write(&rules, "# test\n+http://*.example.com/*\n-re:/[0-9]*5$\n").unwrap();
+    std::fs::write(&hosts, "blocked.example.com\n").unwrap();
+    let uris = [
+        "http://www.example.com/1",
+        "http://www.example.com/5",
+        "http://other.org/2",
+        "http://www.blocked.example.com/3",
+        "http://docs.example.com/4",
+    ];
+    let wet = wet_pages(uris.iter().map(|uri| (uri, "Some text")));
+    let mut urls = UrlFilter::default();
+    urls.add_rules(&rules).unwrap();
+    urls.add_blocklist(&hosts).unwrap();
+    let mut pipeline = Pipeline::default();
+    pipeline.urls = Some(Arc::new(urls));
+    let kept: Vec<String> = run_pipeline(wet.as_bytes(), &pipeline)
+        .await
+        .into_iter()
+        .map(|doc| doc.uri)
+        .collect();
+    assert_eq!(
+        kept,
+        ["http://www.example.com/1", "http://docs.example.com/4"]
+    );
+    let filtered = pipeline.stats.filtered.lock().unwrap();
+    assert_eq!(filtered.get("url-rules"), Some(&2));
+    assert_eq!(filtered.get("blocked-host"), Some(&1));
+}
+
+#[tokio::test]
+async fn wikipedia_abstract_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("abstract.json");
+    let xml = wikipedia_file(1500);
+    wikipedia_abstract::extract_records_and_add_to_json(
+        xml.as_bytes(),
+        out.clone(),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+
+    let docs = read_ndjson_file(&dir.path().join("abstract.wka.json"));
+    assert_eq!(docs.len(), 1500);
+    assert_eq!(docs[1499].title, "Wikipedia: Article 1499");
+    assert_eq!(docs[1499].uri, "https://en.wikipedia.org/wiki/Article_1499");
+}
+
+#[tokio::test]
+async fn pubmed_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("pubmed.json");
+    let xml = pubmed_file(1010);
+    pubmed::extract_records_and_add_to_json(
+        xml.as_bytes(),
+        out.clone(),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+
+    let docs = read_ndjson_file(&dir.path().join("pubmed.wka.json"));
+    assert_eq!(docs.len(), 1010);
+    assert_eq!(docs[0].title, "Title 0");
+    assert_eq!(docs[1009].body, "Abstract 1009");
+    assert_eq!(docs[1009].record_id.as_deref(), Some("1010"));
+    assert_eq!(docs[1009].uri, "https://pubmed.ncbi.nlm.nih.gov/1010/");
+
+    // a <MedlineDate> is used where year, month and day do not fit
+    let dated = [
+        "<Year>2001</Year><Month>Mar</Month><Day>5</Day>",
+        "<MedlineDate>1998 Dec-1999 Jan</MedlineDate>",
+    ];
+    let articles: String = dated
+        .iter()
+        .enumerate()
+        .map(|(i, pub_date)| {
+            format!(
+                "<PubmedArticle><MedlineCitation Status=\"MEDLINE\" Owner=\"NLM\">\
+                 <PMID Version=\"1\">{}</PMID>\
+                 <Article PubModel=\"Print\"><Journal><JournalIssue CitedMedium=\"Print\">\
+                 <PubDate>{}</PubDate></JournalIssue></Journal>\
+                 <ArticleTitle>Dated {}</ArticleTitle>\
+                 <Abstract><AbstractText>Abstract {}</AbstractText></Abstract>\
+                 </Article></MedlineCitation></PubmedArticle>\n",
+                i + 1,
+                pub_date,
+                i,
+                i
+            )
+        })
+        .collect();
+    let xml = format!("<PubmedArticleSet>\n{}</PubmedArticleSet>\n", articles);
+    pubmed::extract_records_and_add_to_json(
+        xml.as_bytes(),
+        dir.path().join("dated.json"),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+    let docs = read_ndjson_file(&dir.path().join("dated.wka.json"));
+    assert_eq!(docs[0].date, "2001-03-05T00:00:00Z");
+    assert_eq!(docs[1].date, "1998-12-01T00:00:00Z");
+}
+
+#[tokio::test]
+async fn pubmed_abstract_sections_and_missing_parts() {
+    let dir = tempfile::tempdir().unwrap();
+    let xml = "<PubmedArticleSet>\n\
+         <PubmedArticle><MedlineCitation Status=\"MEDLINE\" Owner=\"NLM\">\
+         <PMID Version=\"1\">1</PMID><Article PubModel=\"Print\">\
+         <ArticleTitle>Structured</ArticleTitle><Abstract>\
+         <AbstractText Label=\"BACKGROUND\">Why.</AbstractText>\
+         <AbstractText Label=\"METHODS\">How.</AbstractText>\
+         <AbstractText Label=\"RESULTS\">What.</AbstractText>\
+         </Abstract></Article></MedlineCitation></PubmedArticle>\n\
+         <PubmedArticle><MedlineCitation Status=\"MEDLINE\" Owner=\"NLM\">\
+         <PMID Version=\"1\">2</PMID><Article PubModel=\"Print\">\
+         <ArticleTitle>No abstract</ArticleTitle>\
+         </Article></MedlineCitation></PubmedArticle>\n\
+         <PubmedArticle><MedlineCitation Status=\"MEDLINE\" Owner=\"NLM\">\
+         <PMID Version=\"1\">3</PMID><Article PubModel=\"Print\">\
+         <Abstract><AbstractText>No title</AbstractText></Abstract>\
+         </Article></MedlineCitation></PubmedArticle>\n\
+         </PubmedArticleSet>\n";
+    pubmed::extract_records_and_add_to_json(
+        xml.as_bytes(),
+        dir.path().join("pubmed.json"),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+    let docs = read_ndjson_file(&dir.path().join("pubmed.wka.json"));
+    assert_eq!(docs.len(), 3);
+    assert_eq!(docs[0].body, "Why.\nHow.\nWhat.");
+    assert_eq!(
+        (docs[1].title.as_str(), docs[1].body.as_str()),
+        ("No abstract", "")
+    );
+    assert_eq!(
+        (docs[2].title.as_str(), docs[2].body.as_str()),
+        ("", "No title")
+    );
+}
+
+#[tokio::test]
+async fn malformed_abstract_dump_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let error = wikipedia_abstract::extract_records_and_add_to_json(
+        &b"<feed><doc><title>Unclosed"[..],
+        dir.path().join("abstract.json"),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn xml_inputs_decoded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<feed>\n".to_vec();
+    xml.extend_from_slice(
+        b"<doc><title>Wikipedia: Caf\xe9</title>\
+          <url>https://fr.wikipedia.org/wiki/Caf\xe9</url>\
+          <abstract>Le caf\xe9 est une boisson.</abstract></doc>\n</feed>\n",
+    );
+    wikipedia_abstract::extract_records_and_add_to_json(
+        &xml[..],
+        dir.path().join("abstract.json"),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+    let docs = read_ndjson_file(&dir.path().join("abstract.wka.json"));
+    assert_eq!(docs[0].title, "Wikipedia: Caf\u{e9}");
+    assert_eq!(docs[0].body, "Le caf\u{e9} est une boisson.");
+
+    // not UTF-8 despite declaring no other encoding
+    let mut invalid = pubmed_file(1)
+        .replace("Abstract 0", "Abstract ")
+        .into_bytes();
+    let at = invalid
+        .windows(11)
+        .position(|w| w == b"Abstract </")
+        .unwrap()
+        + 9;
+    invalid.insert(at, 0xff);
+    pubmed::extract_records_and_add_to_json(
+        &invalid[..],
+        dir.path().join("pubmed.json"),
+        &OutputOptions::default(),
+        &Pipeline::default(),
+    )
+    .await
+    .unwrap();
+    let docs = read_ndjson_file(&dir.path().join("pubmed.wka.json"));
+    assert_eq!(docs[0].body, "Abstract \u{fffd}");
+}